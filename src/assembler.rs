@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+const START_ADDRESS: usize = 0x200;
+
+/// Operand keywords, e.g. the `DT` in `LD V0, DT`.
+const KEYWORDS: [&str; 10] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R", "LONG"];
+
+/// An assembly error at a 1-based line and column.
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { line: self.line, column: self.column, message }
+    }
+}
+
+enum Item<'a> {
+    Instruction(Token<'a>, Vec<Token<'a>>),
+    Bytes(Vec<Token<'a>>),
+    Sprite(Vec<Token<'a>>),
+}
+
+enum Operand<'a> {
+    Register(u8),
+    Keyword(String),
+    Value(u16, Token<'a>),
+}
+
+/// Assembles source into a ROM image to be loaded at 0x200.
+///
+/// The mnemonics are the ones the disassembler prints. Lines may start with `label:`, comments run
+/// from `;` to the end of the line, and the directives are `:const NAME VALUE`, `:byte VALUE...`
+/// and `:sprite ROW...`, where each row is 8 or 16 characters of `#`/`1` (lit) and `.`/`0` (unlit).
+/// Numbers are decimal, `0x` hexadecimal or `0b` binary.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut symbols: HashMap<&str, u16> = HashMap::new();
+    let mut items = Vec::new();
+    let mut address = START_ADDRESS;
+
+    for (index, line) in source.lines().enumerate() {
+        let mut tokens = tokenize(line, index + 1);
+        if let Some(label) = tokens.first().copied().filter(|token| token.text.ends_with(':') && !token.text.starts_with(':')) {
+            tokens.remove(0);
+            let name = &label.text[..label.text.len() - 1];
+            define(&mut symbols, name, address as u16, label)?;
+        }
+        let first = match tokens.first() {
+            Some(&first) => first,
+            None => continue,
+        };
+        let operands = tokens[1..].to_vec();
+
+        let size = match first.text {
+            ":const" => {
+                let (name, value) = match operands.as_slice() {
+                    [name, value] => (name, value),
+                    _ => return Err(first.error(":const takes a name and a value".to_string())),
+                };
+                let value = evaluate(value, &symbols)?;
+                define(&mut symbols, name.text, value, *name)?;
+                continue;
+            },
+            ":byte" => {
+                let size = operands.len();
+                items.push(Item::Bytes(operands));
+                size
+            },
+            ":sprite" => {
+                let size = operands.iter().map(|row| row.text.len() / 8).sum();
+                items.push(Item::Sprite(operands));
+                size
+            },
+            directive if directive.starts_with(':') => return Err(first.error(format!("unknown directive {}", directive))),
+            _ => {
+                let long = operands.iter().any(|operand| operand.text.eq_ignore_ascii_case("LONG"));
+                items.push(Item::Instruction(first, operands));
+                if long { 4 } else { 2 }
+            },
+        };
+        address += size;
+        if address > 0x10000 {
+            return Err(first.error("program does not fit in memory".to_string()));
+        }
+    }
+
+    let mut rom = Vec::with_capacity(address - START_ADDRESS);
+    for item in &items {
+        match item {
+            Item::Instruction(mnemonic, operands) => {
+                let (instruction, long_address) = encode_instruction(mnemonic, operands, &symbols)?;
+                rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                if let Some(long_address) = long_address {
+                    rom.extend_from_slice(&long_address.to_be_bytes());
+                }
+            },
+            Item::Bytes(values) => {
+                for value in values {
+                    rom.push(limit(evaluate(value, &symbols)?, 0xFF, value)? as u8);
+                }
+            },
+            Item::Sprite(rows) => {
+                for row in rows {
+                    rom.extend_from_slice(&sprite_row(row)?);
+                }
+            },
+        }
+    }
+    Ok(rom)
+}
+
+/// Splits a line into tokens on whitespace and commas, dropping the comment.
+fn tokenize(line: &str, line_number: usize) -> Vec<Token<'_>> {
+    let code = match line.find(';') {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, character) in code.char_indices().chain(std::iter::once((code.len(), ' '))) {
+        let separator = character.is_whitespace() || character == ',';
+        match (start, separator) {
+            (None, false) => start = Some(index),
+            (Some(token_start), true) => {
+                tokens.push(Token { text: &code[token_start..index], line: line_number, column: token_start + 1 });
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    tokens
+}
+
+fn define<'a>(symbols: &mut HashMap<&'a str, u16>, name: &'a str, value: u16, token: Token) -> Result<(), AsmError> {
+    if name.is_empty() || parse_number(name).is_some() || register(name).is_some() {
+        return Err(token.error(format!("invalid name {}", name)));
+    }
+    if KEYWORDS.contains(&name.to_ascii_uppercase().as_str()) {
+        return Err(token.error(format!("{} is a reserved operand name", name)));
+    }
+    if symbols.insert(name, value).is_some() {
+        return Err(token.error(format!("{} is already defined", name)));
+    }
+    Ok(())
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    let text = text.replace('_', "");
+    if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b").or(text.strip_prefix("0B")) {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn evaluate(token: &Token, symbols: &HashMap<&str, u16>) -> Result<u16, AsmError> {
+    parse_number(token.text)
+        .or_else(|| symbols.get(token.text).copied())
+        .ok_or_else(|| token.error(format!("unknown value {}", token.text)))
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut characters = text.chars();
+    match (characters.next(), characters.next(), characters.next()) {
+        (Some('V') | Some('v'), Some(digit), None) => digit.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+fn limit(value: u16, max: u16, token: &Token) -> Result<u16, AsmError> {
+    if value > max {
+        return Err(token.error(format!("{} does not fit in {:#X}", token.text, max)));
+    }
+    Ok(value)
+}
+
+fn sprite_row(row: &Token) -> Result<Vec<u8>, AsmError> {
+    if row.text.len() != 8 && row.text.len() != 16 {
+        return Err(row.error("sprite rows must be 8 or 16 pixels wide".to_string()));
+    }
+    let mut bits: u16 = 0;
+    for (offset, pixel) in row.text.chars().enumerate() {
+        bits = bits << 1 | match pixel {
+            '#' | '1' => 1,
+            '.' | '0' => 0,
+            _ => return Err(AsmError { line: row.line, column: row.column + offset, message: format!("invalid sprite pixel {}", pixel) }),
+        };
+    }
+    Ok(if row.text.len() == 16 { bits.to_be_bytes().to_vec() } else { vec![bits as u8] })
+}
+
+fn encode_instruction<'a>(mnemonic: &Token, operands: &[Token<'a>], symbols: &HashMap<&str, u16>) -> Result<(Instruction, Option<u16>), AsmError> {
+    let mut resolved = Vec::with_capacity(operands.len());
+    for operand in operands {
+        let upper = operand.text.to_ascii_uppercase();
+        resolved.push(if let Some(x) = register(operand.text) {
+            Operand::Register(x)
+        } else if KEYWORDS.contains(&upper.as_str()) {
+            Operand::Keyword(upper)
+        } else {
+            Operand::Value(evaluate(operand, symbols)?, *operand)
+        });
+    }
+
+    let keyword = |operand: &Operand, name: &str| matches!(operand, Operand::Keyword(k) if k == name);
+
+    use Operand::{Register as R, Value as N};
+    let upper = mnemonic.text.to_ascii_uppercase();
+    let instruction = match (upper.as_str(), resolved.as_slice()) {
+        ("CLS", []) => Instruction::ClearScreen,
+        ("RET", []) => Instruction::Return,
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("AUDIO", []) => Instruction::LoadAudioPattern,
+        ("SCD", [N(n, at)]) => Instruction::ScrollDown(limit(*n, 0xF, at)? as u8),
+        ("SCU", [N(n, at)]) => Instruction::ScrollUp(limit(*n, 0xF, at)? as u8),
+        ("PLANE", [N(n, at)]) => Instruction::SelectPlanes(limit(*n, 0xF, at)? as u8),
+        ("JP", [N(nnn, at)]) => Instruction::Jump(limit(*nnn, 0xFFF, at)?),
+        ("JP", [R(0), N(nnn, at)]) => Instruction::JumpWithOffset(limit(*nnn, 0xFFF, at)?, (*nnn >> 8) as u8),
+        ("CALL", [N(nnn, at)]) => Instruction::Call(limit(*nnn, 0xFFF, at)?),
+        ("SE", [R(x), N(nn, at)]) => Instruction::SkipIfEqualValue(*x, limit(*nn, 0xFF, at)? as u8),
+        ("SE", [R(x), R(y)]) => Instruction::SkipIfEqualRegister(*x, *y),
+        ("SNE", [R(x), N(nn, at)]) => Instruction::SkipIfNotEqualValue(*x, limit(*nn, 0xFF, at)? as u8),
+        ("SNE", [R(x), R(y)]) => Instruction::SkipIfNotEqualRegister(*x, *y),
+        ("SAVE", [R(x), R(y)]) => Instruction::SaveRange(*x, *y),
+        ("LOAD", [R(x), R(y)]) => Instruction::LoadRange(*x, *y),
+        ("LD", [R(x), N(nn, at)]) => Instruction::SetValue(*x, limit(*nn, 0xFF, at)? as u8),
+        ("LD", [R(x), R(y)]) => Instruction::SetRegister(*x, *y),
+        ("LD", [i, N(nnn, at)]) if keyword(i, "I") => Instruction::SetIndex(limit(*nnn, 0xFFF, at)?),
+        ("LD", [i, long, N(address, _)]) if keyword(i, "I") && keyword(long, "LONG") => {
+            return Ok((Instruction::SetIndexLong, Some(*address)));
+        },
+        ("LD", [R(x), k]) if keyword(k, "DT") => Instruction::GetDelayTimer(*x),
+        ("LD", [R(x), k]) if keyword(k, "K") => Instruction::WaitKey(*x),
+        ("LD", [R(x), k]) if keyword(k, "[I]") => Instruction::LoadRegisters(*x),
+        ("LD", [R(x), k]) if keyword(k, "R") => Instruction::LoadFlags(*x),
+        ("LD", [k, R(x)]) if keyword(k, "DT") => Instruction::SetDelayTimer(*x),
+        ("LD", [k, R(x)]) if keyword(k, "ST") => Instruction::SetSoundTimer(*x),
+        ("LD", [k, R(x)]) if keyword(k, "F") => Instruction::Font(*x),
+        ("LD", [k, R(x)]) if keyword(k, "HF") => Instruction::BigFont(*x),
+        ("LD", [k, R(x)]) if keyword(k, "B") => Instruction::BinaryToDecimal(*x),
+        ("LD", [k, R(x)]) if keyword(k, "[I]") => Instruction::SaveRegisters(*x),
+        ("LD", [k, R(x)]) if keyword(k, "R") => Instruction::SaveFlags(*x),
+        ("ADD", [R(x), N(nn, at)]) => Instruction::AddValue(*x, limit(*nn, 0xFF, at)? as u8),
+        ("ADD", [R(x), R(y)]) => Instruction::AddRegister(*x, *y),
+        ("ADD", [i, R(x)]) if keyword(i, "I") => Instruction::AddToIndex(*x),
+        ("OR", [R(x), R(y)]) => Instruction::Or(*x, *y),
+        ("AND", [R(x), R(y)]) => Instruction::And(*x, *y),
+        ("XOR", [R(x), R(y)]) => Instruction::Xor(*x, *y),
+        ("SUB", [R(x), R(y)]) => Instruction::Subtract(*x, *y),
+        ("SUBN", [R(x), R(y)]) => Instruction::SubtractReversed(*x, *y),
+        ("SHR", [R(x)]) => Instruction::ShiftRight(*x, *x),
+        ("SHR", [R(x), R(y)]) => Instruction::ShiftRight(*x, *y),
+        ("SHL", [R(x)]) => Instruction::ShiftLeft(*x, *x),
+        ("SHL", [R(x), R(y)]) => Instruction::ShiftLeft(*x, *y),
+        ("RND", [R(x), N(nn, at)]) => Instruction::Random(*x, limit(*nn, 0xFF, at)? as u8),
+        ("DRW", [R(x), R(y), N(n, at)]) => Instruction::Draw(*x, *y, limit(*n, 0xF, at)? as u8),
+        ("SKP", [R(x)]) => Instruction::SkipIfKey(*x),
+        ("SKNP", [R(x)]) => Instruction::SkipIfNotKey(*x),
+        ("PITCH", [R(x)]) => Instruction::SetPitch(*x),
+        (
+            "CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO" | "SCD" | "SCU" | "PLANE" | "JP" | "CALL"
+            | "SE" | "SNE" | "SAVE" | "LOAD" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL"
+            | "RND" | "DRW" | "SKP" | "SKNP" | "PITCH",
+            _,
+        ) => return Err(mnemonic.error(format!("invalid operands for {}", upper))),
+        _ => return Err(mnemonic.error(format!("unknown instruction {}", mnemonic.text))),
+    };
+    Ok((instruction, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    fn error_at(source: &str) -> (usize, usize, String) {
+        let error = assemble(source).unwrap_err();
+        (error.line, error.column, error.message)
+    }
+
+    #[test]
+    fn assembles_labels_and_constants() {
+        let source = "\
+:const SPEED 3
+start:
+    LD V0, SPEED
+    JP start
+";
+        assert_eq!(assemble(source).unwrap(), vec![0x60, 0x03, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let position = |source| {
+            let (line, column, _) = error_at(source);
+            (line, column)
+        };
+        assert_eq!(position("CLS\n    JP nowhere"), (2, 8));
+        assert_eq!(position("CLS\n  LD V0, 0x100"), (2, 10));
+        assert_eq!(position("  FROB V0"), (1, 3));
+    }
+
+    #[test]
+    fn rejects_keywords_as_names() {
+        let (line, column, message) = error_at("CLS\n:const f 3");
+        assert_eq!((line, column), (2, 8));
+        assert!(message.contains("reserved"), "{}", message);
+        assert_eq!(error_at("  CLS\n  b: RET").1, 3);
+        assert_eq!(error_at("Long: RET").1, 1);
+    }
+
+    #[test]
+    fn rejects_duplicate_labels() {
+        assert_eq!(error_at("loop: CLS\nloop: RET").0, 2);
+    }
+}
@@ -0,0 +1,153 @@
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+/// Shape of the plain CHIP-8 tone.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    /// A lopsided, stepped wave, harsher than a square wave.
+    Buzz,
+}
+
+pub const WAVEFORM_NAMES: &str = "square, sine, triangle, buzz";
+
+/// One cycle of the buzz: hand-picked steps that roughly mimic a cheap piezo buzzer, played back
+/// without interpolation.
+const BUZZ: [f32; 16] = [
+    0.9, 1.0, 0.7, 0.8, 0.6, 0.5, 0.6, 0.2, -0.9, -1.0, -0.6, -0.8, -0.5, -0.6, -0.3, -0.1,
+];
+
+/// How long the tone takes to fade in and out, so starting and stopping it doesn't click.
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "buzz" => Some(Waveform::Buzz),
+            _ => None,
+        }
+    }
+
+    /// The wave's value `phase` of the way through a cycle, from -1 to 1.
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Buzz => BUZZ[(phase * BUZZ.len() as f32) as usize % BUZZ.len()],
+        }
+    }
+}
+
+/// Synthesizes the tone, or an XO-CHIP sample pattern once a ROM loads one, one emulated frame
+/// at a time, so a beep lasts exactly as many frames as the sound timer ran.
+pub struct Tone {
+    sample_rate: u32,
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    pattern: Option<[u8; 16]>,
+    /// Pattern bits played per second.
+    pattern_rate: f32,
+    /// How far through the current cycle the tone is, from 0 to 1, or through the pattern, from 0 to 128.
+    phase: f32,
+    /// Envelope gain, ramping towards 1 while beeping and back to 0 after.
+    level: f32,
+    muted: bool,
+    /// Frames rendered so far, to spread the fraction of a sample each frame leaves over.
+    frames: u64,
+}
+
+impl Tone {
+    pub fn new(sample_rate: u32, waveform: Waveform, frequency: f32, volume: f32) -> Tone {
+        Tone {
+            sample_rate,
+            waveform,
+            frequency,
+            volume,
+            pattern: None,
+            pattern_rate: 4000.0,
+            phase: 0.0,
+            level: 0.0,
+            muted: false,
+            frames: 0,
+        }
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Plays `pattern` at the XO-CHIP `pitch` instead of the tone, or goes back to the tone on `None`.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if pattern.is_some() != self.pattern.is_some() {
+            self.phase = 0.0;
+        }
+        self.pattern = pattern;
+        self.pattern_rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+    }
+
+    /// The samples for one 60 Hz frame: the tone if `beeping`, fading to silence otherwise.
+    pub fn render_frame(&mut self, beeping: bool) -> Vec<f32> {
+        let rate = self.sample_rate as u64;
+        let length = ((self.frames + 1) * rate / 60 - self.frames * rate / 60) as usize;
+        self.frames += 1;
+        let ramp = 1.0 / (ENVELOPE_SECONDS * self.sample_rate as f32);
+        let target = if beeping && !self.muted { 1.0 } else { 0.0 };
+        let mut out = vec![0.0; length];
+        for sample in out.iter_mut() {
+            self.level = if self.level < target { (self.level + ramp).min(target) } else { (self.level - ramp).max(target) };
+            if self.level == 0.0 {
+                // Start every beep at the same point in the wave.
+                self.phase = 0.0;
+                *sample = 0.0;
+                continue;
+            }
+            *sample = self.volume * self.level * self.next_sample();
+        }
+        out
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        match self.pattern {
+            Some(pattern) => {
+                let bit = self.phase as usize % 128;
+                self.phase = (self.phase + self.pattern_rate / self.sample_rate as f32) % 128.0;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+            },
+            None => {
+                let value = self.waveform.sample(self.phase);
+                self.phase = (self.phase + self.frequency / self.sample_rate as f32) % 1.0;
+                value
+            },
+        }
+    }
+}
+
+/// Writes mono samples from -1 to 1 as a 16-bit PCM WAV file.
+pub fn write_wav(path: &Path, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let data_size = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    std::fs::write(path, bytes)
+}
@@ -0,0 +1,298 @@
+use std::path::Path;
+
+use toml::{Table, Value};
+
+use crate::audio::{self, Waveform};
+use crate::palette::{self, Palette};
+
+/// Read when `--config` is not given, if it exists.
+pub const DEFAULT_PATH: &str = "chip8.toml";
+
+pub const LAYOUT_NAMES: &str = "qwerty, azerty, numpad";
+
+/// SDL key names for CHIP-8 keys 0-F. The letter block sits under the 1-4 row:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D      Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+const QWERTY: [&str; 16] = ["X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V"];
+/// SDL names the number row 1-0 on every layout, so only the letters move.
+const AZERTY: [&str; 16] = ["X", "1", "2", "3", "A", "Z", "E", "Q", "S", "D", "W", "C", "4", "R", "F", "V"];
+const NUMPAD: [&str; 16] = [
+    "Keypad .", "Keypad 7", "Keypad 8", "Keypad 9", "Keypad 4", "Keypad 5", "Keypad 6", "Keypad 1",
+    "Keypad 2", "Keypad 3", "Keypad 0", "Keypad Enter", "Keypad /", "Keypad *", "Keypad -", "Keypad +",
+];
+
+/// SDL game controller buttons and the CHIP-8 keys they press, before the config file is applied.
+const GAMEPAD: [(&str, u8); 8] = [
+    ("dpup", 0x2), ("dpdown", 0x8), ("dpleft", 0x4), ("dpright", 0x6),
+    ("a", 0x5), ("b", 0x0), ("leftshoulder", 0x1), ("rightshoulder", 0x3),
+];
+
+fn layout(name: &str) -> Option<[&'static str; 16]> {
+    match name {
+        "qwerty" => Some(QWERTY),
+        "azerty" => Some(AZERTY),
+        "numpad" => Some(NUMPAD),
+        _ => None,
+    }
+}
+
+/// Settings from the TOML config file, with the running ROM's overrides applied.
+///
+/// ```toml
+/// [keypad]
+/// layout = "azerty"
+/// A = "Tab"               # CHIP-8 key A
+///
+/// [gamepad]
+/// a = "7"                 # button = CHIP-8 key, "" unbinds
+///
+/// [display]
+/// palette = "amber"       # or a list of 2 to 4 "#RRGGBB" colors
+/// persistence = 0.6       # phosphor decay per frame, 0 to turn it off
+///
+/// [audio]
+/// waveform = "square"     # square, sine, triangle or buzz
+/// tone = 440              # Hz
+/// volume = 30             # percent
+///
+/// [rom."PONG.ch8".keypad] # by ROM file name
+/// 1 = "Up"
+/// 4 = "Down"
+///
+/// [rom."PONG.ch8".gamepad]
+/// dpup = "1"
+/// dpdown = "4"
+/// ```
+///
+/// Setting `layout` starts over from that preset, dropping keys remapped by an earlier level.
+pub struct Config {
+    /// Key names for CHIP-8 keys 0-F.
+    pub keypad: [String; 16],
+    /// Game controller button names and the CHIP-8 keys they press.
+    pub gamepad: Vec<(String, u8)>,
+    pub palette: Option<Palette>,
+    pub persistence: Option<f32>,
+    pub waveform: Option<Waveform>,
+    /// Tone frequency in Hz.
+    pub tone: Option<f32>,
+    /// From 0 to 1.
+    pub volume: Option<f32>,
+}
+
+impl Config {
+    /// Loads `path`, or `DEFAULT_PATH` if it exists. `layout` is the `--keypad` preset, which wins over the file.
+    pub fn load(path: Option<&str>, rom_path: &str, layout: Option<&str>) -> Result<Config, String> {
+        let table = match path {
+            Some(path) => read(path)?,
+            None if Path::new(DEFAULT_PATH).exists() => read(DEFAULT_PATH)?,
+            None => Table::new(),
+        };
+        Config::from_table(&table, rom_path, layout)
+    }
+
+    /// Applies the global settings in `table`, then the ROM's, then `layout`.
+    fn from_table(table: &Table, rom_path: &str, layout: Option<&str>) -> Result<Config, String> {
+        let rom_name = Path::new(rom_path).file_name().and_then(|name| name.to_str()).unwrap_or(rom_path);
+        let rom = match table.get("rom").and_then(|roms| roms.get(rom_name)) {
+            Some(Value::Table(rom)) => rom.clone(),
+            Some(_) => return Err(format!("rom.\"{}\" must be a table", rom_name)),
+            None => Table::new(),
+        };
+
+        let mut keypad = merge(section(table, "keypad")?, section(&rom, "keypad")?);
+        if let Some(layout) = layout {
+            keypad = Table::from_iter([("layout".to_string(), Value::String(layout.to_string()))]);
+        }
+
+        let mut gamepad = Table::from_iter(GAMEPAD.map(|(button, key)| (button.to_string(), Value::Integer(key as i64))));
+        gamepad.extend(section(table, "gamepad")?);
+        gamepad.extend(section(&rom, "gamepad")?);
+
+        let mut display = section(table, "display")?;
+        display.extend(section(&rom, "display")?);
+
+        let mut sound = section(table, "audio")?;
+        sound.extend(section(&rom, "audio")?);
+
+        Ok(Config {
+            keypad: parse_keypad(&keypad)?,
+            gamepad: parse_gamepad(&gamepad)?,
+            palette: display.get("palette").map(parse_palette).transpose()?,
+            persistence: display.get("persistence").map(parse_persistence).transpose()?,
+            waveform: sound.get("waveform").map(parse_waveform).transpose()?,
+            tone: sound.get("tone").map(|tone| parse_number(tone, "tone", 1.0, 20000.0)).transpose()?,
+            volume: sound.get("volume").map(|volume| parse_number(volume, "volume", 0.0, 100.0)).transpose()?
+                .map(|percent| percent / 100.0),
+        })
+    }
+}
+
+fn read(path: &str) -> Result<Table, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    text.parse().map_err(|error| format!("{}: {}", path, error))
+}
+
+fn section(table: &Table, name: &str) -> Result<Table, String> {
+    match table.get(name) {
+        Some(Value::Table(section)) => Ok(section.clone()),
+        Some(_) => Err(format!("{} must be a table", name)),
+        None => Ok(Table::new()),
+    }
+}
+
+/// Applies a ROM's section over the global one. A `layout` in the ROM's section replaces it outright.
+fn merge(mut global: Table, rom: Table) -> Table {
+    if rom.contains_key("layout") {
+        return rom;
+    }
+    global.extend(rom);
+    global
+}
+
+fn parse_keypad(table: &Table) -> Result<[String; 16], String> {
+    let preset = match table.get("layout") {
+        Some(Value::String(name)) => layout(name).ok_or(format!("keypad layout must be one of {}", LAYOUT_NAMES))?,
+        Some(_) => return Err("keypad layout must be a string".to_string()),
+        None => QWERTY,
+    };
+    let mut keys = preset.map(String::from);
+    for (name, value) in table {
+        if name == "layout" {
+            continue;
+        }
+        let key = u8::from_str_radix(name, 16).ok().filter(|&key| key < 16)
+            .ok_or(format!("unknown keypad key {}, expected 0-F", name))?;
+        keys[key as usize] = match value {
+            Value::String(key_name) => key_name.clone(),
+            _ => return Err(format!("keypad key {} must be a key name", name)),
+        };
+    }
+    Ok(keys)
+}
+
+fn parse_gamepad(table: &Table) -> Result<Vec<(String, u8)>, String> {
+    let mut bindings = Vec::new();
+    for (button, value) in table {
+        let key = match value {
+            Value::String(key) if key.is_empty() => continue,
+            Value::String(key) => u8::from_str_radix(key, 16).ok(),
+            Value::Integer(key) => u8::try_from(*key).ok(),
+            _ => None,
+        };
+        match key.filter(|&key| key < 16) {
+            Some(key) => bindings.push((button.clone(), key)),
+            None => return Err(format!("gamepad button {} must be bound to a CHIP-8 key 0-F", button)),
+        }
+    }
+    Ok(bindings)
+}
+
+fn parse_palette(value: &Value) -> Result<Palette, String> {
+    match value {
+        Value::String(name) => Palette::parse(name),
+        Value::Array(colors) => {
+            let colors = colors.iter()
+                .map(|color| color.as_str().ok_or("palette colors must be strings".to_string()).and_then(palette::parse_color))
+                .collect::<Result<Vec<_>, _>>()?;
+            Palette::from_colors(&colors)
+        },
+        _ => Err("palette must be a name or a list of colors".to_string()),
+    }
+}
+
+fn parse_persistence(value: &Value) -> Result<f32, String> {
+    let decay = match value {
+        Value::Float(decay) => *decay,
+        Value::Integer(decay) => *decay as f64,
+        _ => f64::NAN,
+    };
+    if (0.0..1.0).contains(&decay) {
+        Ok(decay as f32)
+    } else {
+        Err("persistence must be a number from 0 up to 1".to_string())
+    }
+}
+
+fn parse_waveform(value: &Value) -> Result<Waveform, String> {
+    value.as_str().and_then(Waveform::from_name)
+        .ok_or(format!("waveform must be one of {}", audio::WAVEFORM_NAMES))
+}
+
+fn parse_number(value: &Value, name: &str, min: f64, max: f64) -> Result<f32, String> {
+    let number = match value {
+        Value::Float(number) => *number,
+        Value::Integer(number) => *number as f64,
+        _ => f64::NAN,
+    };
+    if (min..=max).contains(&number) {
+        Ok(number as f32)
+    } else {
+        Err(format!("{} must be a number from {} to {}", name, min, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        [keypad]
+        layout = "azerty"
+        0 = "Tab"
+        1 = "F1"
+
+        [audio]
+        tone = 220
+
+        [rom."PONG.ch8".keypad]
+        1 = "Up"
+
+        [rom."PONG.ch8".audio]
+        volume = 50
+
+        [rom."MAZE.ch8".keypad]
+        layout = "numpad"
+        2 = "Up"
+    "#;
+
+    fn config(rom_path: &str, layout: Option<&str>) -> Config {
+        Config::from_table(&FILE.parse().unwrap(), rom_path, layout).unwrap()
+    }
+
+    #[test]
+    fn global_settings_apply_to_every_rom() {
+        let config = config("roms/TETRIS.ch8", None);
+        assert_eq!(config.keypad[0x0], "Tab");
+        assert_eq!(config.keypad[0x1], "F1");
+        assert_eq!(config.keypad[0x4], "A");
+        assert_eq!((config.tone, config.volume), (Some(220.0), None));
+    }
+
+    #[test]
+    fn rom_settings_override_global_ones() {
+        let config = config("roms/PONG.ch8", None);
+        assert_eq!(config.keypad[0x0], "Tab");
+        assert_eq!(config.keypad[0x1], "Up");
+        assert_eq!((config.tone, config.volume), (Some(220.0), Some(0.5)));
+    }
+
+    #[test]
+    fn rom_layout_drops_global_remapping() {
+        let config = config("roms/MAZE.ch8", None);
+        assert_eq!(config.keypad[0x0], "Keypad .");
+        assert_eq!(config.keypad[0x1], "Keypad 7");
+        assert_eq!(config.keypad[0x2], "Up");
+    }
+
+    #[test]
+    fn keypad_option_wins_over_the_file() {
+        for rom_path in ["roms/TETRIS.ch8", "roms/PONG.ch8", "roms/MAZE.ch8"] {
+            assert_eq!(config(rom_path, Some("qwerty")).keypad, QWERTY.map(String::from));
+        }
+    }
+}
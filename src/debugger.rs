@@ -0,0 +1,251 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::instruction::Instruction;
+use crate::processor::{EmuError, CPU};
+
+const HELP: &str = "\
+Commands:
+  c, continue        resume execution
+  p, pause           pause execution
+  s, step [N]        execute N instructions (default 1)
+  n, next            step, running 2NNN calls to completion
+  f, finish          run until the current subroutine returns
+  b, break ADDR      set a breakpoint at ADDR
+  d, delete ADDR     remove the breakpoint at ADDR
+  w, watch ADDR      pause when ADDR is written to
+  u, unwatch ADDR    remove the watchpoint on ADDR
+  l, list            list breakpoints and watchpoints
+  r, regs            show registers, stack and timers
+  m, mem ADDR [LEN]  dump LEN bytes of memory (default 16)
+  h, help            show this help
+Addresses are hexadecimal, with or without a 0x prefix.";
+
+/// A REPL on stdin that controls the CPU while the frontend keeps running.
+pub struct Debugger {
+    commands: Receiver<String>,
+    paused: bool,
+    /// When set, run until the stack is back down to this many entries, to step over or finish a call.
+    run_until_depth: Option<usize>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    /// Starts reading commands from stdin on a background thread. The CPU starts paused.
+    pub fn new() -> Debugger {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+        });
+        println!("Debugger started, execution paused. Type `help` for commands.");
+        prompt();
+        Debugger {
+            commands,
+            paused: true,
+            run_until_depth: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Handles every command typed since the last call.
+    pub fn poll_commands(&mut self, cpu: &mut CPU) {
+        while let Ok(line) = self.commands.try_recv() {
+            self.handle_command(cpu, &line);
+            prompt();
+        }
+    }
+
+    /// Checks breakpoints, watchpoints and step targets after the CPU has executed an instruction.
+    pub fn after_step(&mut self, cpu: &CPU) {
+        if let Some(address) = cpu.memory_writes().iter().find(|address| self.watchpoints.contains(address)) {
+            println!("\nWatchpoint: {:#05x} written by the instruction at {:#05x}", address, cpu.last_instruction_address());
+            self.stop(cpu);
+        } else if self.breakpoints.contains(&cpu.pc()) {
+            println!("\nBreakpoint at {:#05x}", cpu.pc());
+            self.stop(cpu);
+        } else if let Some(depth) = self.run_until_depth {
+            if cpu.stack().len() <= depth {
+                println!();
+                self.stop(cpu);
+            }
+        }
+    }
+
+    /// Pauses on a fault so the machine state can be inspected.
+    pub fn fault(&mut self, cpu: &CPU, error: &EmuError) {
+        println!("\nFault: {}", error);
+        self.stop(cpu);
+    }
+
+    fn stop(&mut self, cpu: &CPU) {
+        self.paused = true;
+        self.run_until_depth = None;
+        print_registers(cpu);
+        prompt();
+    }
+
+    fn handle_command(&mut self, cpu: &mut CPU, line: &str) {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return,
+        };
+        let argument = words.next();
+
+        match command {
+            "c" | "continue" => {
+                self.paused = false;
+                self.run_until_depth = None;
+            },
+            "p" | "pause" => {
+                self.paused = true;
+                self.run_until_depth = None;
+                print_registers(cpu);
+            },
+            "s" | "step" => {
+                let count = match argument {
+                    Some(count) => match count.parse::<u32>() {
+                        Ok(count) => count,
+                        Err(_) => return println!("Invalid count: {}", count),
+                    },
+                    None => 1,
+                };
+                self.paused = true;
+                for _ in 0..count {
+                    if let Err(error) = cpu.step() {
+                        println!("Fault: {}", error);
+                        break;
+                    }
+                }
+                print_registers(cpu);
+            },
+            "n" | "next" => {
+                if cpu.peek_instruction() & 0xF000 == 0x2000 {
+                    self.run_until_depth = Some(cpu.stack().len());
+                    self.paused = false;
+                } else {
+                    if let Err(error) = cpu.step() {
+                        println!("Fault: {}", error);
+                    }
+                    print_registers(cpu);
+                }
+            },
+            "f" | "finish" => match cpu.stack().len() {
+                0 => println!("Not inside a subroutine"),
+                depth => {
+                    self.run_until_depth = Some(depth - 1);
+                    self.paused = false;
+                },
+            },
+            "b" | "break" => {
+                if let Some(address) = parse_address(argument) {
+                    self.breakpoints.insert(address);
+                    println!("Breakpoint set at {:#05x}", address);
+                }
+            },
+            "d" | "delete" => {
+                if let Some(address) = parse_address(argument) {
+                    if !self.breakpoints.remove(&address) {
+                        println!("No breakpoint at {:#05x}", address);
+                    }
+                }
+            },
+            "w" | "watch" => {
+                if let Some(address) = parse_address(argument) {
+                    self.watchpoints.insert(address);
+                    println!("Watching writes to {:#05x}", address);
+                }
+            },
+            "u" | "unwatch" => {
+                if let Some(address) = parse_address(argument) {
+                    if !self.watchpoints.remove(&address) {
+                        println!("No watchpoint on {:#05x}", address);
+                    }
+                }
+            },
+            "l" | "list" => {
+                for address in &self.breakpoints {
+                    println!("break {:#05x}", address);
+                }
+                for address in &self.watchpoints {
+                    println!("watch {:#05x}", address);
+                }
+            },
+            "r" | "regs" => print_registers(cpu),
+            "m" | "mem" => {
+                if let Some(address) = parse_address(argument) {
+                    let length = words.next().and_then(|length| length.parse().ok()).unwrap_or(16);
+                    print_memory(cpu, address as usize, length);
+                }
+            },
+            "h" | "help" => println!("{}", HELP),
+            _ => println!("Unknown command `{}`. Type `help` for commands.", command),
+        }
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    io::stdout().flush().ok();
+}
+
+fn parse_address(argument: Option<&str>) -> Option<u16> {
+    let argument = match argument {
+        Some(argument) => argument,
+        None => {
+            println!("Missing address");
+            return None;
+        },
+    };
+    let digits = argument.trim_start_matches("0x").trim_start_matches("0X");
+    match u16::from_str_radix(digits, 16) {
+        Ok(address) => Some(address),
+        Err(_) => {
+            println!("Invalid address: {}", argument);
+            None
+        },
+    }
+}
+
+fn print_registers(cpu: &CPU) {
+    let (delay_timer, sound_timer) = cpu.timers();
+    let next = cpu.peek_instruction();
+    println!("PC {:#05x}  next {:04x} {}", cpu.pc(), next, Instruction::decode(next));
+    println!("I {:#05x}  DT {:3}  ST {:3}{}", cpu.index_register(), delay_timer, sound_timer,
+             if cpu.is_waiting_for_key() { "  waiting for key" } else { "" });
+    let registers = cpu.registers();
+    for (half, values) in registers.chunks(8).enumerate() {
+        let line: Vec<String> = values.iter().enumerate()
+            .map(|(i, value)| format!("V{:X} {:02x}", half * 8 + i, value))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+    let stack: Vec<String> = cpu.stack().iter().map(|address| format!("{:#05x}", address)).collect();
+    println!("Stack [{}]  {}/{}", stack.join(", "), stack.len(), cpu.stack_depth());
+}
+
+fn print_memory(cpu: &CPU, address: usize, length: usize) {
+    let memory = cpu.memory();
+    let end = address.saturating_add(length).min(memory.len());
+    for start in (address..end).step_by(16) {
+        let bytes: Vec<String> = memory[start..end.min(start + 16)].iter().map(|byte| format!("{:02x}", byte)).collect();
+        println!("{:#06x}: {}", start, bytes.join(" "));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::instruction::Instruction;
+
+const START_ADDRESS: u16 = 0x200;
+const BYTES_PER_DATA_LINE: usize = 6;
+
+/// Disassembles a ROM loaded at 0x200, following jumps and calls from the entry point to tell
+/// code from data. Jump and call targets are labelled; everything unreachable is emitted as
+/// `:byte` data.
+pub fn disassemble(rom: &[u8]) -> String {
+    let end = START_ADDRESS as usize + rom.len();
+    let word_at = |address: u16| -> Option<u16> {
+        let offset = (address - START_ADDRESS) as usize;
+        Some(((*rom.get(offset)? as u16) << 8) | *rom.get(offset + 1)? as u16)
+    };
+
+    let mut code = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut pending = vec![START_ADDRESS];
+
+    while let Some(mut address) = pending.pop() {
+        while address >= START_ADDRESS && !code.contains_key(&address) {
+            let instruction = match word_at(address) {
+                Some(opcode) => Instruction::decode(opcode),
+                None => break,
+            };
+            if let Instruction::Unknown(_) = instruction {
+                break;
+            }
+            code.insert(address, instruction);
+            let next = address.wrapping_add(instruction.size());
+
+            match instruction {
+                Instruction::Jump(target) | Instruction::JumpWithOffset(target, _) => {
+                    targets.insert(target);
+                    pending.push(target);
+                    break;
+                },
+                Instruction::Call(target) => {
+                    targets.insert(target);
+                    pending.push(target);
+                },
+                Instruction::Return | Instruction::Exit => break,
+                Instruction::SkipIfEqualValue(..) | Instruction::SkipIfNotEqualValue(..)
+                | Instruction::SkipIfEqualRegister(..) | Instruction::SkipIfNotEqualRegister(..)
+                | Instruction::SkipIfKey(_) | Instruction::SkipIfNotKey(_) => {
+                    if let Some(skipped) = word_at(next) {
+                        pending.push(next.wrapping_add(Instruction::decode(skipped).size()));
+                    }
+                },
+                _ => {},
+            }
+            address = next;
+        }
+    }
+
+    // A jump into the middle of an instruction lands on an address that never starts a line, so
+    // it can't carry a label; such targets are printed as plain addresses instead.
+    let mut line_starts = BTreeSet::new();
+    let mut address = START_ADDRESS as usize;
+    while address < end {
+        match code.get(&(address as u16)) {
+            Some(instruction) => {
+                line_starts.insert(address as u16);
+                address += instruction.size() as usize;
+            },
+            None => address += 1,
+        }
+    }
+
+    let labels: BTreeMap<u16, String> = targets.iter()
+        .filter(|target| line_starts.contains(target))
+        .map(|&target| (target, format!("label_{:03X}", target)))
+        .collect();
+    let address_name = |address: u16| match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("{:#05X}", address),
+    };
+
+    let mut output = String::new();
+    let mut address = START_ADDRESS as usize;
+    while address < end {
+        match code.get(&(address as u16)) {
+            Some(instruction) => {
+                if let Some(label) = labels.get(&(address as u16)) {
+                    output.push_str(&format!("{}:\n", label));
+                }
+                let mnemonic = match instruction {
+                    Instruction::SetIndexLong => match word_at(address as u16 + 2) {
+                        Some(long_address) => format!("LD I, LONG {:#06X}", long_address),
+                        None => instruction.render(&address_name),
+                    },
+                    _ => instruction.render(&address_name),
+                };
+                output.push_str(&format!("    {:<36}; {:03X}\n", mnemonic, address));
+                address += instruction.size() as usize;
+            },
+            None => {
+                let start = address;
+                let mut bytes = Vec::new();
+                while address < end && bytes.len() < BYTES_PER_DATA_LINE && !code.contains_key(&(address as u16)) {
+                    bytes.push(format!("{:#04X}", rom[address - START_ADDRESS as usize]));
+                    address += 1;
+                }
+                output.push_str(&format!("    {:<36}; {:03X}\n", format!(":byte {}", bytes.join(" ")), start));
+            },
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::disassemble;
+    use crate::assembler::assemble;
+
+    fn round_trip(rom: &[u8]) {
+        let listing = disassemble(rom);
+        match assemble(&listing) {
+            Ok(assembled) => assert_eq!(assembled, rom, "listing:\n{}", listing),
+            Err(error) => panic!("{}\nlisting:\n{}", error, listing),
+        }
+    }
+
+    #[test]
+    fn ibm_logo_reassembles() {
+        round_trip(include_bytes!("../ROMs/IBM.ch8"));
+    }
+
+    #[test]
+    fn jump_into_the_middle_of_an_instruction_reassembles() {
+        // 200: CALL 0x203, into the second byte of the LD V0, 0x12 at 202, where 12 00 is JP 0x200.
+        round_trip(&[0x22, 0x03, 0x60, 0x12, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn random_roms_reassemble() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            let rom: Vec<u8> = (0..rng.gen_range(1..1024)).map(|_| rng.gen()).collect();
+            round_trip(&rom);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANE_COUNT: usize = 2;
+
+/// The screen, made of two XO-CHIP bit-planes. Each row is a bitmask where bit `x` is column `x`.
+///
+/// Plain CHIP-8 and SUPER-CHIP programs only ever draw on the first plane.
+#[derive(Clone, PartialEq)]
+pub struct Framebuffer {
+    planes: [[u128; HIRES_HEIGHT]; PLANE_COUNT],
+    /// Bitmask of the planes that drawing, clearing and scrolling apply to.
+    selected_planes: u8,
+    hires: bool,
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            planes: [[0; HIRES_HEIGHT]; PLANE_COUNT],
+            selected_planes: 1,
+            hires: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+
+    /// Switches between 64x32 and 128x64, clearing every plane like SUPER-CHIP and XO-CHIP do.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[0; HIRES_HEIGHT]; PLANE_COUNT];
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & 0b11;
+    }
+
+    /// The color of a pixel: bit 0 is set if it is lit on the first plane, bit 1 on the second.
+    pub fn color(&self, x: usize, y: usize) -> usize {
+        (0..PLANE_COUNT)
+            .filter(|&plane| self.planes[plane][y] & (1 << x) != 0)
+            .fold(0, |color, plane| color | 1 << plane)
+    }
+
+    /// XORs a lit pixel onto `plane`. Returns `true` if it erased one that was already lit.
+    pub fn flip(&mut self, plane: usize, x: usize, y: usize) -> bool {
+        let was_set = self.planes[plane][y] & (1 << x) != 0;
+        self.planes[plane][y] ^= 1 << x;
+        was_set
+    }
+
+    pub fn clear(&mut self) {
+        for plane in self.selected_mut() {
+            *plane = [0; HIRES_HEIGHT];
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let height = self.height();
+        let n = n.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(0..height - n, n);
+            plane[..n].fill(0);
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        let n = n.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(n..height, 0);
+            plane[height - n..height].fill(0);
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        for plane in self.selected_mut() {
+            for row in plane.iter_mut() {
+                *row >>= n;
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let mask = self.row_mask();
+        for plane in self.selected_mut() {
+            for row in plane.iter_mut() {
+                *row = (*row << n) & mask;
+            }
+        }
+    }
+
+    /// The raw planes, selected plane mask and resolution, for save states.
+    pub fn parts(&self) -> (&[[u128; HIRES_HEIGHT]; PLANE_COUNT], u8, bool) {
+        (&self.planes, self.selected_planes, self.hires)
+    }
+
+    pub fn from_parts(planes: [[u128; HIRES_HEIGHT]; PLANE_COUNT], selected_planes: u8, hires: bool) -> Framebuffer {
+        Framebuffer {
+            planes,
+            selected_planes: selected_planes & 0b11,
+            hires,
+        }
+    }
+
+    fn selected_mut(&mut self) -> impl Iterator<Item = &mut [u128; HIRES_HEIGHT]> {
+        let selected = self.selected_planes;
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(plane, _)| selected & (1 << plane) != 0)
+            .map(|(_, rows)| rows)
+    }
+
+    fn row_mask(&self) -> u128 {
+        if self.hires { u128::MAX } else { (1 << LORES_WIDTH) - 1 }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
@@ -0,0 +1,42 @@
+use crate::framebuffer::Framebuffer;
+use crate::palette::Palette;
+
+/// Shows the framebuffer, once per 60 Hz frame.
+pub trait Screen {
+    fn draw(&mut self, framebuffer: &Framebuffer);
+    fn set_palette(&mut self, palette: &Palette);
+    fn toggle_fullscreen(&mut self);
+}
+
+/// Plays the CHIP-8 tone while the sound timer is running.
+pub trait Beeper {
+    /// Plays one emulated 60 Hz frame of sound: the tone if `beeping`, silence otherwise.
+    fn play_frame(&mut self, beeping: bool);
+    /// Replaces the tone with an XO-CHIP 128-bit sample pattern played back at `pitch`,
+    /// or goes back to the plain tone when `pattern` is `None`.
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8);
+    fn set_muted(&mut self, muted: bool);
+}
+
+/// Supplies the state of the 16-key hex keypad.
+pub trait Keypad {
+    /// Processes pending input. Returns `false` once the user has asked to quit.
+    fn update(&mut self) -> bool;
+    fn is_key_down(&self, key: u8) -> bool;
+    /// Takes the emulator hotkeys pressed since the last call.
+    fn take_hotkeys(&mut self) -> Vec<Hotkey>;
+    /// Whether the rewind key is held down, stepping back a frame every frame.
+    fn is_rewind_held(&self) -> bool;
+}
+
+/// Emulator commands bound to keys outside the CHIP-8 keypad.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    SaveState(u8),
+    LoadState(u8),
+    SpeedUp,
+    SlowDown,
+    NextPalette,
+    ToggleFullscreen,
+    ToggleMute,
+}
@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::audio::Tone;
+use crate::framebuffer::Framebuffer;
+use crate::frontend::{Beeper, Hotkey, Keypad, Screen};
+use crate::palette::Palette;
+
+/// Frontend with no window, audio or input, for running ROMs from scripts and CI.
+pub struct Headless;
+
+impl Screen for Headless {
+    fn draw(&mut self, _framebuffer: &Framebuffer) {}
+
+    fn set_palette(&mut self, _palette: &Palette) {}
+
+    fn toggle_fullscreen(&mut self) {}
+}
+
+impl Beeper for Headless {
+    fn play_frame(&mut self, _beeping: bool) {}
+
+    fn set_pattern(&mut self, _pattern: Option<[u8; 16]>, _pitch: u8) {}
+
+    fn set_muted(&mut self, _muted: bool) {}
+}
+
+/// Records the sound instead of playing it, for writing to a WAV file.
+pub struct AudioRecorder {
+    tone: Tone,
+    samples: Rc<RefCell<Vec<f32>>>,
+}
+
+impl AudioRecorder {
+    /// Appends every frame's samples to `samples`.
+    pub fn new(tone: Tone, samples: Rc<RefCell<Vec<f32>>>) -> AudioRecorder {
+        AudioRecorder { tone, samples }
+    }
+}
+
+impl Beeper for AudioRecorder {
+    fn play_frame(&mut self, beeping: bool) {
+        self.samples.borrow_mut().extend(self.tone.render_frame(beeping));
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.tone.set_pattern(pattern, pitch);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.tone.set_muted(muted);
+    }
+}
+
+impl Keypad for Headless {
+    fn update(&mut self) -> bool {
+        true
+    }
+
+    fn is_key_down(&self, _key: u8) -> bool {
+        false
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+
+    fn is_rewind_held(&self) -> bool {
+        false
+    }
+}
+
+const PIXEL_CHARACTERS: [char; 4] = ['.', '#', '+', '@'];
+
+/// Renders the framebuffer as text, one line per row. Unlit pixels are `.` and pixels lit on the
+/// first plane `#`; XO-CHIP pixels lit on the second plane are `+`, or `@` when on both.
+pub fn render_text(framebuffer: &Framebuffer) -> String {
+    let mut text = String::with_capacity((framebuffer.width() + 1) * framebuffer.height());
+    for row in 0..framebuffer.height() {
+        for column in 0..framebuffer.width() {
+            text.push(PIXEL_CHARACTERS[framebuffer.color(column, row)]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Waveform;
+    use crate::processor::CPU;
+
+    #[test]
+    fn recorder_beeps_for_as_many_frames_as_the_sound_timer() {
+        let sample_rate = 22050;
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let tone = Tone::new(sample_rate, Waveform::Square, 440.0, 0.5);
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(AudioRecorder::new(tone, samples.clone())), Box::new(Headless));
+        // LD V0, 10; LD ST, V0; JP 0x204
+        cpu.load(vec![0x60, 0x0A, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+
+        let mut frames = Vec::new();
+        for _ in 0..120 {
+            let start = samples.borrow().len();
+            cpu.run_frame(10).unwrap();
+            frames.push(samples.borrow()[start..].to_vec());
+        }
+        // The release ramp spills into the frame after the beep, but only the beeping frames end on a sound.
+        let beeping: Vec<bool> = frames.iter().map(|frame| *frame.last().unwrap() != 0.0).collect();
+        assert_eq!(beeping, (0..120).map(|frame| frame < 10).collect::<Vec<_>>());
+        assert!(frames[..10].iter().all(|frame| frame.iter().all(|&sample| sample != 0.0)));
+        assert!(frames[11..].iter().flatten().all(|&sample| sample == 0.0));
+
+        // 367.5 samples a frame: the halves are spread out so every 60 frames make exactly one second.
+        for second in frames.chunks(60) {
+            assert_eq!(second.iter().map(Vec::len).sum::<usize>(), sample_rate as usize);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt;
+
+/// A decoded instruction. `x` and `y` are register numbers, `n`/`nn` immediates and `nnn` addresses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instruction {
+    /// 00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00DN (XO-CHIP)
+    ScrollUp(u8),
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    LowRes,
+    /// 00FF (SUPER-CHIP)
+    HighRes,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipIfEqualValue(u8, u8),
+    /// 4XNN
+    SkipIfNotEqualValue(u8, u8),
+    /// 5XY0
+    SkipIfEqualRegister(u8, u8),
+    /// 5XY2 (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 5XY3 (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6XNN
+    SetValue(u8, u8),
+    /// 7XNN
+    AddValue(u8, u8),
+    /// 8XY0
+    SetRegister(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    AddRegister(u8, u8),
+    /// 8XY5
+    Subtract(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7
+    SubtractReversed(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0
+    SkipIfNotEqualRegister(u8, u8),
+    /// ANNN
+    SetIndex(u16),
+    /// BNNN; X is only used by the CHIP-48 jump quirk.
+    JumpWithOffset(u16, u8),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipIfKey(u8),
+    /// EXA1
+    SkipIfNotKey(u8),
+    /// F000 NNNN (XO-CHIP); the address is the word after the opcode.
+    SetIndexLong,
+    /// FN01 (XO-CHIP)
+    SelectPlanes(u8),
+    /// F002 (XO-CHIP)
+    LoadAudioPattern,
+    /// FX07
+    GetDelayTimer(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelayTimer(u8),
+    /// FX18
+    SetSoundTimer(u8),
+    /// FX1E
+    AddToIndex(u8),
+    /// FX29
+    Font(u8),
+    /// FX30 (SUPER-CHIP)
+    BigFont(u8),
+    /// FX33
+    BinaryToDecimal(u8),
+    /// FX3A (XO-CHIP)
+    SetPitch(u8),
+    /// FX55
+    SaveRegisters(u8),
+    /// FX65
+    LoadRegisters(u8),
+    /// FX75 (SUPER-CHIP)
+    SaveFlags(u8),
+    /// FX85 (SUPER-CHIP)
+    LoadFlags(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match (opcode & 0xF000) >> 12 {
+            0x0 => match opcode {
+                0x00E0 => Instruction::ClearScreen,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LowRes,
+                0x00FF => Instruction::HighRes,
+                _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown(n),
+                _ if opcode & 0xFFF0 == 0x00D0 => Instruction::ScrollUp(n),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipIfEqualValue(x, nn),
+            0x4 => Instruction::SkipIfNotEqualValue(x, nn),
+            0x5 => match n {
+                0x0 => Instruction::SkipIfEqualRegister(x, y),
+                0x2 => Instruction::SaveRange(x, y),
+                0x3 => Instruction::LoadRange(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x6 => Instruction::SetValue(x, nn),
+            0x7 => Instruction::AddValue(x, nn),
+            0x8 => match n {
+                0x0 => Instruction::SetRegister(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddRegister(x, y),
+                0x5 => Instruction::Subtract(x, y),
+                0x6 => Instruction::ShiftRight(x, y),
+                0x7 => Instruction::SubtractReversed(x, y),
+                0xE => Instruction::ShiftLeft(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9 if n == 0 => Instruction::SkipIfNotEqualRegister(x, y),
+            0xA => Instruction::SetIndex(nnn),
+            0xB => Instruction::JumpWithOffset(nnn, x),
+            0xC => Instruction::Random(x, nn),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match nn {
+                0x9E => Instruction::SkipIfKey(x),
+                0xA1 => Instruction::SkipIfNotKey(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF => match nn {
+                0x00 if x == 0 => Instruction::SetIndexLong,
+                0x01 => Instruction::SelectPlanes(x),
+                0x02 if x == 0 => Instruction::LoadAudioPattern,
+                0x07 => Instruction::GetDelayTimer(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelayTimer(x),
+                0x18 => Instruction::SetSoundTimer(x),
+                0x1E => Instruction::AddToIndex(x),
+                0x29 => Instruction::Font(x),
+                0x30 => Instruction::BigFont(x),
+                0x33 => Instruction::BinaryToDecimal(x),
+                0x3A => Instruction::SetPitch(x),
+                0x55 => Instruction::SaveRegisters(x),
+                0x65 => Instruction::LoadRegisters(x),
+                0x75 => Instruction::SaveFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    /// The opcode word; the inverse of `decode`. F000's address word is not included.
+    pub fn encode(&self) -> u16 {
+        let xy = |opcode: u16, x: u8, y: u8| opcode | (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |opcode: u16, x: u8, nn: u8| opcode | (x as u16) << 8 | nn as u16;
+        let x_only = |opcode: u16, x: u8| opcode | (x as u16) << 8;
+
+        match *self {
+            Instruction::ScrollDown(n) => 0x00C0 | n as u16,
+            Instruction::ScrollUp(n) => 0x00D0 | n as u16,
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipIfEqualValue(x, nn) => xnn(0x3000, x, nn),
+            Instruction::SkipIfNotEqualValue(x, nn) => xnn(0x4000, x, nn),
+            Instruction::SkipIfEqualRegister(x, y) => xy(0x5000, x, y),
+            Instruction::SaveRange(x, y) => xy(0x5002, x, y),
+            Instruction::LoadRange(x, y) => xy(0x5003, x, y),
+            Instruction::SetValue(x, nn) => xnn(0x6000, x, nn),
+            Instruction::AddValue(x, nn) => xnn(0x7000, x, nn),
+            Instruction::SetRegister(x, y) => xy(0x8000, x, y),
+            Instruction::Or(x, y) => xy(0x8001, x, y),
+            Instruction::And(x, y) => xy(0x8002, x, y),
+            Instruction::Xor(x, y) => xy(0x8003, x, y),
+            Instruction::AddRegister(x, y) => xy(0x8004, x, y),
+            Instruction::Subtract(x, y) => xy(0x8005, x, y),
+            Instruction::ShiftRight(x, y) => xy(0x8006, x, y),
+            Instruction::SubtractReversed(x, y) => xy(0x8007, x, y),
+            Instruction::ShiftLeft(x, y) => xy(0x800E, x, y),
+            Instruction::SkipIfNotEqualRegister(x, y) => xy(0x9000, x, y),
+            Instruction::SetIndex(nnn) => 0xA000 | nnn,
+            Instruction::JumpWithOffset(nnn, _) => 0xB000 | nnn,
+            Instruction::Random(x, nn) => xnn(0xC000, x, nn),
+            Instruction::Draw(x, y, n) => xy(0xD000, x, y) | n as u16,
+            Instruction::SkipIfKey(x) => x_only(0xE09E, x),
+            Instruction::SkipIfNotKey(x) => x_only(0xE0A1, x),
+            Instruction::SetIndexLong => 0xF000,
+            Instruction::SelectPlanes(n) => x_only(0xF001, n),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::GetDelayTimer(x) => x_only(0xF007, x),
+            Instruction::WaitKey(x) => x_only(0xF00A, x),
+            Instruction::SetDelayTimer(x) => x_only(0xF015, x),
+            Instruction::SetSoundTimer(x) => x_only(0xF018, x),
+            Instruction::AddToIndex(x) => x_only(0xF01E, x),
+            Instruction::Font(x) => x_only(0xF029, x),
+            Instruction::BigFont(x) => x_only(0xF030, x),
+            Instruction::BinaryToDecimal(x) => x_only(0xF033, x),
+            Instruction::SetPitch(x) => x_only(0xF03A, x),
+            Instruction::SaveRegisters(x) => x_only(0xF055, x),
+            Instruction::LoadRegisters(x) => x_only(0xF065, x),
+            Instruction::SaveFlags(x) => x_only(0xF075, x),
+            Instruction::LoadFlags(x) => x_only(0xF085, x),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Size in bytes, including the address word that follows F000.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::SetIndexLong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Renders the mnemonic, naming addresses with `address_name`, e.g. to substitute labels.
+    pub fn render(&self, address_name: &dyn Fn(u16) -> String) -> String {
+        match *self {
+            Instruction::ScrollDown(n) => format!("SCD {}", n),
+            Instruction::ScrollUp(n) => format!("SCU {}", n),
+            Instruction::ClearScreen => "CLS".to_string(),
+            Instruction::Return => "RET".to_string(),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowRes => "LOW".to_string(),
+            Instruction::HighRes => "HIGH".to_string(),
+            Instruction::Jump(nnn) => format!("JP {}", address_name(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", address_name(nnn)),
+            Instruction::SkipIfEqualValue(x, nn) => format!("SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfNotEqualValue(x, nn) => format!("SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipIfEqualRegister(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+            Instruction::SetValue(x, nn) => format!("LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddValue(x, nn) => format!("ADD V{:X}, {:#04X}", x, nn),
+            Instruction::SetRegister(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegister(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::Subtract(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::SubtractReversed(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfNotEqualRegister(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndex(nnn) => format!("LD I, {}", address_name(nnn)),
+            Instruction::JumpWithOffset(nnn, _) => format!("JP V0, {}", address_name(nnn)),
+            Instruction::Random(x, nn) => format!("RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfKey(x) => format!("SKP V{:X}", x),
+            Instruction::SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+            Instruction::SetIndexLong => "LD I, LONG".to_string(),
+            Instruction::SelectPlanes(n) => format!("PLANE {}", n),
+            Instruction::LoadAudioPattern => "AUDIO".to_string(),
+            Instruction::GetDelayTimer(x) => format!("LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => format!("LD V{:X}, K", x),
+            Instruction::SetDelayTimer(x) => format!("LD DT, V{:X}", x),
+            Instruction::SetSoundTimer(x) => format!("LD ST, V{:X}", x),
+            Instruction::AddToIndex(x) => format!("ADD I, V{:X}", x),
+            Instruction::Font(x) => format!("LD F, V{:X}", x),
+            Instruction::BigFont(x) => format!("LD HF, V{:X}", x),
+            Instruction::BinaryToDecimal(x) => format!("LD B, V{:X}", x),
+            Instruction::SetPitch(x) => format!("PITCH V{:X}", x),
+            Instruction::SaveRegisters(x) => format!("LD [I], V{:X}", x),
+            Instruction::LoadRegisters(x) => format!("LD V{:X}, [I]", x),
+            Instruction::SaveFlags(x) => format!("LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => format!("LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => format!(":byte {:#04X} {:#04X}", opcode >> 8, opcode & 0xFF),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&|address| format!("{:#05X}", address)))
+    }
+}
@@ -1,89 +1,334 @@
 mod processor;
+mod assembler;
+mod audio;
+mod config;
+mod debugger;
+mod disassembler;
+mod instruction;
+mod quirks;
+mod rewind;
+mod savestate;
 mod font;
+mod framebuffer;
+mod frontend;
+mod headless;
+mod options;
+mod palette;
+mod scheduler;
+mod sdl_frontend;
 
+use std::cell::RefCell;
 use std::env::{self};
+use std::path::Path;
+use std::rc::Rc;
 
 use sdl2::pixels::Color;
-use sdl2::event::Event;
-use std::time::Duration;
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
-use std::f32::consts::PI;
+use sdl2::audio::AudioSpecDesired;
 
-const SLEEP_TIME: u64 = 2;
+use audio::{Tone, Waveform};
 
-struct SineWave {
-    phase: f32,
-    frequency: f32,
-    volume: f32,
+use headless::{AudioRecorder, Headless};
+use debugger::Debugger;
+use framebuffer::{LORES_HEIGHT, LORES_WIDTH};
+use frontend::{Beeper, Hotkey};
+use config::Config;
+use options::Options;
+use palette::Palette;
+use rewind::Rewind;
+use scheduler::FrameScheduler;
+use sdl_frontend::{SdlBeeper, SdlKeypad, SdlScreen};
+
+const MAX_IPF: u32 = 100_000;
+const SAMPLE_RATE: u32 = 44100;
+
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => return disassemble(&args),
+        Some("asm") => return assemble(&args),
+        _ => {},
+    }
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, options::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let rom = std::fs::read(&options.rom_path).expect("Unable to load ROM");
+
+    if options.headless {
+        run_headless(&options, rom);
+    } else {
+        run_sdl(&options, rom);
+    }
 }
 
-impl AudioCallback for SineWave {
-    type Channel = f32;
+fn disassemble(args: &[String]) {
+    let path = match args.get(2) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: chip8 disasm <rom>");
+            std::process::exit(1);
+        }
+    };
+    let rom = std::fs::read(path).expect("Unable to load ROM");
+    print!("{}", disassembler::disassemble(&rom));
+}
 
-    fn callback(&mut self, out: &mut [f32]) {
-        const SAMPLE_RATE: f32 = 44100.0;
-        let angular_frequency = 2.0 * PI * self.frequency / SAMPLE_RATE;
-        
-        for x in out.iter_mut() {
-            *x = self.volume * (self.phase * angular_frequency).sin();
-            self.phase += 1.0;
+fn assemble(args: &[String]) {
+    let (path, output) = match &args[2..] {
+        [path] => (path, Path::new(path).with_extension("ch8")),
+        [path, flag, output] if flag == "-o" => (path, output.into()),
+        _ => {
+            eprintln!("Usage: chip8 asm <source> [-o <rom>]");
+            std::process::exit(1);
+        }
+    };
+    let source = std::fs::read_to_string(path).expect("Unable to load source");
+    match assembler::assemble(&source) {
+        Ok(rom) => std::fs::write(&output, rom).expect("Unable to write ROM"),
+        Err(error) => {
+            eprintln!("{}:{}", path, error);
+            std::process::exit(1);
         }
     }
 }
 
-pub fn main() {
-    let args: Vec<String> = env::args().collect();
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+fn run_headless(options: &Options, rom: Vec<u8>) {
+    let samples = Rc::new(RefCell::new(Vec::new()));
+    let beeper: Box<dyn Beeper> = match options.wav_path {
+        Some(_) => {
+            let config = Config::load(options.config_path.as_deref(), &options.rom_path, None)
+                .unwrap_or_else(|error| exit_with_error(&error));
+            Box::new(AudioRecorder::new(make_tone(options, &config, SAMPLE_RATE), samples.clone()))
+        },
+        None => Box::new(Headless),
+    };
+    let mut cpu = processor::CPU::new(Box::new(Headless), beeper, Box::new(Headless));
+    cpu.seed_random(options.seed.unwrap_or(0));
+    cpu.set_quirks(options.quirks);
+    cpu.set_font(&options.font, options.big_font_address);
+    load_rom(&mut cpu, rom);
+
+    let mut fault = None;
+    for _ in 0..options.frames {
+        if cpu.is_halted() {
+            break;
+        }
+        if let Err(error) = cpu.run_frame(options.ipf) {
+            fault = Some(error);
+            break;
+        }
+    }
+    print!("{}", headless::render_text(cpu.framebuffer()));
+    if let Some(path) = &options.wav_path {
+        if let Err(error) = audio::write_wav(Path::new(path), SAMPLE_RATE, &samples.borrow()) {
+            exit_with_error(&format!("Unable to write {}: {}", path, error));
+        }
+    }
+    if let Some(error) = fault {
+        eprintln!("CPU fault: {}", error);
+        std::process::exit(1);
+    }
+}
 
-    if args.len() < 2 {
-        panic!("Please input a path to a ROM file.");
+/// The tone from the command line, else the config file, else a 440 Hz sine at half volume.
+fn make_tone(options: &Options, config: &Config, sample_rate: u32) -> Tone {
+    let waveform = options.waveform.or(config.waveform).unwrap_or(Waveform::Sine);
+    let tone = options.tone.or(config.tone).unwrap_or(440.0);
+    let volume = options.volume.or(config.volume).unwrap_or(0.5);
+    Tone::new(sample_rate, waveform, tone, volume)
+}
+
+fn load_rom(cpu: &mut processor::CPU, rom: Vec<u8>) {
+    if let Err(error) = cpu.load(rom) {
+        eprintln!("Unable to load ROM: {}", error);
+        std::process::exit(1);
     }
-    let rom = std::fs::read(&args[1]).expect("Unable to load ROM");
-    
+}
+
+/// Emulator settings that hotkeys change while the ROM runs.
+struct Session<'a> {
+    rom_path: &'a str,
+    rom_hash: u64,
+    ipf: u32,
+    palettes: Vec<Palette>,
+    palette: usize,
+    muted: bool,
+}
+
+fn run_sdl(options: &Options, rom: Vec<u8>) {
+    let config = Config::load(options.config_path.as_deref(), &options.rom_path, options.keypad_layout.as_deref())
+        .unwrap_or_else(|error| exit_with_error(&error));
+    let palette = options.palette.clone().or(config.palette.clone()).unwrap_or_default();
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    let window = video_subsystem.window("CHIP-8 Emu", 1280, 640)
+    let window = video_subsystem.window("CHIP-8 Emu", LORES_WIDTH as u32 * options.scale, LORES_HEIGHT as u32 * options.scale)
         .position_centered()
+        .resizable()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
-    
-    canvas.set_scale(20.0, 20.0).expect("Failed to set scale");
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+
+    let [red, green, blue] = palette.colors[0];
+    canvas.set_draw_color(Color::RGB(red, green, blue));
     canvas.clear();
     canvas.present();
 
     let audio_subsystem = sdl_context.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
+        freq: Some(SAMPLE_RATE as i32),
         channels: Some(1),
         samples: None,
     };
 
-    let device = audio_subsystem.open_playback(None, &desired_spec, |_spec| {
-        SineWave {
-            phase: 0.0,
-            frequency: 440.0,
-            volume: 0.5,
-        }
-    }).unwrap();
+    let queue = audio_subsystem.open_queue::<f32, _>(None, &desired_spec).unwrap();
+    // SDL may not open the rate asked for; synthesize at the one it did.
+    let tone = make_tone(options, &config, queue.spec().freq as u32);
+
+    let persistence = options.persistence.or(config.persistence).unwrap_or(0.0);
+    let screen = SdlScreen::new(canvas, &palette, options.integer_scale, persistence);
+    let beeper = SdlBeeper::new(queue, tone);
+    let keypad = SdlKeypad::new(sdl_context.event_pump().unwrap(), sdl_context.game_controller().unwrap(), &config)
+        .unwrap_or_else(|error| exit_with_error(&error));
 
-    let mut cpu = processor::CPU::new(&mut canvas, device, sdl_context);
-    cpu.load(rom);
+    let mut cpu = processor::CPU::new(Box::new(screen), Box::new(beeper), Box::new(keypad));
+    if let Some(seed) = options.seed {
+        cpu.seed_random(seed);
+    }
+    cpu.set_quirks(options.quirks);
+    cpu.set_font(&options.font, options.big_font_address);
+    let rom_hash = savestate::rom_hash(&rom);
+    load_rom(&mut cpu, rom);
 
-    'running: loop {
+    let mut debugger = if options.debug { Some(Debugger::new()) } else { None };
 
-        for event in cpu.event.poll_iter() {
-            if let Event::Quit { .. } = event {
-                break 'running;
-            };
+    // F10 cycles through the presets, starting from a custom palette if one was given.
+    let mut palettes = Palette::presets();
+    let palette = palettes.iter().position(|preset| *preset == palette).unwrap_or_else(|| {
+        palettes.insert(0, palette);
+        0
+    });
+    let mut session = Session { rom_path: &options.rom_path, rom_hash, ipf: options.ipf, palettes, palette, muted: false };
+    // After a fault the window stays open on the last frame; loading a save state resumes.
+    let mut faulted = false;
+    let mut rewind = Rewind::new(options.rewind_seconds as usize * 60);
+    let mut scheduler = FrameScheduler::new();
+    loop {
+        let frames = scheduler.wait();
+        if !cpu.poll_input() || cpu.is_halted() {
+            break;
+        }
+        for hotkey in cpu.take_hotkeys() {
+            faulted &= !handle_hotkey(&mut cpu, hotkey, &mut session);
+        }
+        if let Some(debugger) = debugger.as_mut() {
+            debugger.poll_commands(&mut cpu);
         }
-        
-        cpu.run();
+        for _ in 0..frames {
+            if cpu.is_rewind_held() {
+                // Stepping back out of a fault lets the ROM run again.
+                if let Some(state) = rewind.step_back() {
+                    cpu.restore_state(&state);
+                    faulted = false;
+                }
+                continue;
+            }
+            match debugger.as_mut() {
+                Some(debugger) => {
+                    // Step one instruction at a time so breakpoints can stop mid-frame.
+                    for _ in 0..session.ipf {
+                        if debugger.is_paused() {
+                            break;
+                        }
+                        match cpu.step() {
+                            Ok(()) => debugger.after_step(&cpu),
+                            Err(error) => debugger.fault(&cpu, &error),
+                        }
+                    }
+                    if !debugger.is_paused() {
+                        cpu.end_frame();
+                        rewind.record(&cpu.save_state());
+                    }
+                },
+                None if !faulted => {
+                    match cpu.run_frame(session.ipf) {
+                        Ok(_) => rewind.record(&cpu.save_state()),
+                        Err(error) => {
+                            eprintln!("CPU fault: {}", error);
+                            faulted = true;
+                        },
+                    }
+                },
+                None => {},
+            }
+        }
+        // Drawn even while paused or faulted, so resizing and palette changes show up.
+        cpu.draw();
+    }
+}
+
+/// Steps the instructions per frame up or down by about a quarter.
+fn change_speed(ipf: u32, faster: bool) -> u32 {
+    let step = (ipf / 4).max(1);
+    let ipf = if faster { ipf.saturating_add(step).min(MAX_IPF) } else { ipf.saturating_sub(step).max(1) };
+    println!("Speed: {} instructions per frame", ipf);
+    ipf
+}
 
-        ::std::thread::sleep(Duration::from_millis(SLEEP_TIME));
+/// Returns `true` if the hotkey replaced the machine state.
+fn handle_hotkey(cpu: &mut processor::CPU, hotkey: Hotkey, session: &mut Session) -> bool {
+    match hotkey {
+        Hotkey::SaveState(slot) => {
+            match savestate::save(&savestate::slot_path(session.rom_path, slot), session.rom_hash, &cpu.save_state()) {
+                Ok(()) => println!("Saved state to slot {}", slot),
+                Err(error) => eprintln!("Failed to save slot {}: {}", slot, error),
+            }
+            false
+        },
+        Hotkey::LoadState(slot) => {
+            match savestate::load(&savestate::slot_path(session.rom_path, slot), session.rom_hash) {
+                Ok(state) => {
+                    cpu.restore_state(&state);
+                    println!("Loaded state from slot {}", slot);
+                    true
+                },
+                Err(error) => {
+                    eprintln!("Failed to load slot {}: {}", slot, error);
+                    false
+                },
+            }
+        },
+        Hotkey::SpeedUp | Hotkey::SlowDown => {
+            session.ipf = change_speed(session.ipf, hotkey == Hotkey::SpeedUp);
+            false
+        },
+        Hotkey::ToggleFullscreen => {
+            cpu.toggle_fullscreen();
+            false
+        },
+        Hotkey::ToggleMute => {
+            session.muted = !session.muted;
+            cpu.set_muted(session.muted);
+            println!("Sound: {}", if session.muted { "muted" } else { "on" });
+            false
+        },
+        Hotkey::NextPalette => {
+            session.palette = (session.palette + 1) % session.palettes.len();
+            let palette = &session.palettes[session.palette];
+            cpu.set_palette(palette);
+            println!("Palette: {}", palette.name);
+            false
+        },
     }
 }
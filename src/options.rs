@@ -0,0 +1,192 @@
+use crate::audio::{self, Waveform};
+use crate::config;
+use crate::font::{self, Font};
+use crate::palette::{self, Palette};
+use crate::processor::BIG_FONT_ADDRESS;
+use crate::quirks::{self, JumpTypes, Quirks};
+
+pub struct Options {
+    pub rom_path: String,
+    pub headless: bool,
+    /// Where headless runs write their sound.
+    pub wav_path: Option<String>,
+    pub frames: u32,
+    pub seed: Option<u64>,
+    pub quirks: Quirks,
+    pub debug: bool,
+    /// Instructions executed per 60 Hz frame.
+    pub ipf: u32,
+    pub config_path: Option<String>,
+    /// Keypad preset overriding the config file.
+    pub keypad_layout: Option<String>,
+    /// Palette overriding the config file.
+    pub palette: Option<Palette>,
+    /// Initial window size, in window pixels per low-resolution CHIP-8 pixel.
+    pub scale: u32,
+    pub integer_scale: bool,
+    /// Phosphor decay overriding the config file.
+    pub persistence: Option<f32>,
+    pub waveform: Option<Waveform>,
+    /// Tone frequency in Hz.
+    pub tone: Option<f32>,
+    /// From 0 to 1.
+    pub volume: Option<f32>,
+    pub font: Font,
+    pub big_font_address: u16,
+    /// How far back holding the rewind key goes; 0 turns recording off.
+    pub rewind_seconds: u32,
+}
+
+pub const USAGE: &str = "\
+Usage: chip8 disasm <rom>
+       chip8 asm <source> [-o <rom>]
+       chip8 [--headless [--wav FILE]] [--debug] [--frames N] [--seed N] [--ipf N | --hz N]
+             [--quirks PRESET] [--jump vip|schip] [--stack-depth N] [--stack-in-memory]
+             [--config FILE] [--keypad LAYOUT] [--palette PALETTE] [--scale N] [--scaling fit|integer]
+             [--persistence DECAY] [--font NAME|FILE] [--big-font-address ADDR]
+             [--waveform WAVE] [--tone HZ] [--volume PERCENT] [--rewind SECONDS] <rom>";
+
+const DEFAULT_FRAMES: u32 = 600;
+const DEFAULT_IPF: u32 = 10;
+const DEFAULT_SCALE: u32 = 20;
+const DEFAULT_REWIND_SECONDS: u32 = 10;
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+/// A preset name, or else a font file.
+fn parse_font(value: &str) -> Result<Font, String> {
+    if let Some(font) = Font::from_name(value) {
+        return Ok(font);
+    }
+    let bytes = std::fs::read(value)
+        .map_err(|error| format!("--font must be one of {} or a font file; {}: {}", font::PRESET_NAMES, value, error))?;
+    Font::from_bytes(&bytes).map_err(|error| format!("{}: {}", value, error))
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut headless = false;
+        let mut wav_path = None;
+        let mut debug = false;
+        let mut frames = DEFAULT_FRAMES;
+        let mut seed = None;
+        let mut quirks = Quirks::default();
+        let mut jump_type = None;
+        let mut stack_depth = None;
+        let mut stack_in_memory = false;
+        let mut ipf = DEFAULT_IPF;
+        let mut config_path = None;
+        let mut keypad_layout = None;
+        let mut palette = None;
+        let mut scale = DEFAULT_SCALE;
+        let mut integer_scale = false;
+        let mut persistence = None;
+        let mut waveform = None;
+        let mut tone = None;
+        let mut volume = None;
+        let mut font = Font::default();
+        let mut big_font_address = BIG_FONT_ADDRESS;
+        let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--wav" => wav_path = Some(parse_value(arg, args.next())?),
+                "--debug" => debug = true,
+                "--frames" => frames = parse_value(arg, args.next())?,
+                "--seed" => seed = Some(parse_value(arg, args.next())?),
+                "--quirks" => quirks = args.next().and_then(|name| Quirks::from_name(name))
+                    .ok_or(format!("--quirks must be one of {}", quirks::PRESET_NAMES))?,
+                "--jump" => jump_type = match args.next().map(String::as_str) {
+                    Some("vip") => Some(JumpTypes::V0),
+                    Some("schip") => Some(JumpTypes::VX),
+                    _ => return Err("--jump must be vip or schip".to_string()),
+                },
+                "--ipf" => ipf = parse_value(arg, args.next())?,
+                "--hz" => ipf = (parse_value::<u32>(arg, args.next())? + 30) / 60,
+                "--config" => config_path = Some(parse_value(arg, args.next())?),
+                "--keypad" => keypad_layout = match args.next() {
+                    Some(layout) if config::LAYOUT_NAMES.split(", ").any(|name| name == layout) => Some(layout.clone()),
+                    _ => return Err(format!("--keypad must be one of {}", config::LAYOUT_NAMES)),
+                },
+                "--palette" => palette = Some(Palette::parse(args.next().ok_or("--palette needs a value")?)
+                    .map_err(|_| format!("--palette must be one of {} or hex colors like #000000,#FFFFFF", palette::PRESET_NAMES))?),
+                "--scale" => scale = parse_value(arg, args.next())?,
+                "--scaling" => integer_scale = match args.next().map(String::as_str) {
+                    Some("fit") => false,
+                    Some("integer") => true,
+                    _ => return Err("--scaling must be fit or integer".to_string()),
+                },
+                "--persistence" => persistence = Some(parse_value(arg, args.next())?),
+                "--waveform" => waveform = Some(args.next().and_then(|name| Waveform::from_name(name))
+                    .ok_or(format!("--waveform must be one of {}", audio::WAVEFORM_NAMES))?),
+                "--tone" => tone = Some(parse_value(arg, args.next())?),
+                "--volume" => volume = Some(parse_value::<f32>(arg, args.next())? / 100.0),
+                "--font" => font = parse_font(args.next().ok_or("--font needs a value")?)?,
+                "--big-font-address" => big_font_address = args.next()
+                    .and_then(|address| u16::from_str_radix(address.trim_start_matches("0x").trim_start_matches("0X"), 16).ok())
+                    .filter(|address| (BIG_FONT_ADDRESS..=0x200 - 160).contains(address))
+                    .ok_or("--big-font-address must be a hex address from 0A0 to 160, between the small font and the program")?,
+                "--rewind" => rewind_seconds = parse_value(arg, args.next())?,
+                "--stack-depth" => stack_depth = Some(parse_value(arg, args.next())?),
+                "--stack-in-memory" => stack_in_memory = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                path => rom_path = Some(path.to_string()),
+            }
+        }
+
+        if let Some(jump_type) = jump_type {
+            quirks.jump = jump_type;
+        }
+        if wav_path.is_some() && !headless {
+            return Err("--wav only works with --headless".to_string());
+        }
+        if scale == 0 {
+            return Err("--scale must be at least 1".to_string());
+        }
+        if persistence.is_some_and(|decay: f32| !(0.0..1.0).contains(&decay)) {
+            return Err("--persistence must be from 0 up to 1".to_string());
+        }
+        if tone.is_some_and(|tone: f32| !(1.0..=20000.0).contains(&tone)) {
+            return Err("--tone must be from 1 to 20000 Hz".to_string());
+        }
+        if volume.is_some_and(|volume: f32| !(0.0..=1.0).contains(&volume)) {
+            return Err("--volume must be from 0 to 100".to_string());
+        }
+        if ipf == 0 {
+            return Err("speed must be at least one instruction per frame".to_string());
+        }
+        if let Some(stack_depth) = stack_depth {
+            quirks.stack_depth = stack_depth;
+        }
+        quirks.stack_in_memory |= stack_in_memory;
+
+        Ok(Options {
+            rom_path: rom_path.ok_or("Please input a path to a ROM file.")?,
+            headless,
+            wav_path,
+            frames,
+            seed,
+            quirks,
+            debug,
+            ipf,
+            config_path,
+            keypad_layout,
+            palette,
+            scale,
+            integer_scale,
+            persistence,
+            waveform,
+            tone,
+            volume,
+            font,
+            big_font_address,
+            rewind_seconds,
+        })
+    }
+}
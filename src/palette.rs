@@ -0,0 +1,60 @@
+/// Screen colors, indexed by `Framebuffer::color`: background, first plane, second plane, both planes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [[u8; 3]; 4],
+}
+
+pub const PRESET_NAMES: &str = "mono, green, amber, lcd, octo";
+
+const PRESETS: [(&str, [[u8; 3]; 4]); 5] = [
+    ("mono", [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]]),
+    ("green", [[0x0B, 0x1A, 0x0B], [0x33, 0xFF, 0x33], [0x1A, 0x8C, 0x1A], [0x99, 0xFF, 0x99]]),
+    ("amber", [[0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00], [0x99, 0x5F, 0x00], [0xFF, 0xD6, 0x8A]]),
+    ("lcd", [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]]),
+    ("octo", [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]]),
+];
+
+impl Palette {
+    pub fn presets() -> Vec<Palette> {
+        PRESETS.iter().map(|&(name, colors)| Palette { name: name.to_string(), colors }).collect()
+    }
+
+    /// A preset name, or two to four comma-separated `RRGGBB` colors with an optional `#`.
+    /// With only two colors, the XO-CHIP plane colors are blended from them.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        if let Some(preset) = Palette::presets().into_iter().find(|preset| preset.name == text) {
+            return Ok(preset);
+        }
+        let colors = text.split(',').map(parse_color).collect::<Result<Vec<_>, _>>()?;
+        Palette::from_colors(&colors)
+    }
+
+    pub fn from_colors(colors: &[[u8; 3]]) -> Result<Palette, String> {
+        let colors = match *colors {
+            [background, foreground] => [background, foreground, blend(background, foreground, 2), blend(background, foreground, 1)],
+            [background, first, second] => [background, first, second, blend(first, second, 2)],
+            [background, first, second, both] => [background, first, second, both],
+            _ => return Err(format!("a palette is one of {} or 2 to 4 hex colors", PRESET_NAMES)),
+        };
+        Ok(Palette { name: "custom".to_string(), colors })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::presets().remove(0)
+    }
+}
+
+pub fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    let hex = text.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6).ok_or(format!("invalid color {}", text))?;
+    let [_, red, green, blue] = value.to_be_bytes();
+    Ok([red, green, blue])
+}
+
+/// `thirds`/3 of the way from `from` to `to`.
+fn blend(from: [u8; 3], to: [u8; 3], thirds: u16) -> [u8; 3] {
+    [0, 1, 2].map(|i| ((from[i] as u16 * (3 - thirds) + to[i] as u16 * thirds) / 3) as u8)
+}
@@ -1,78 +1,280 @@
-use sdl2::{audio::AudioDevice, keyboard::Scancode, render::Canvas};
-
-use crate::{font::{self, FONT}, SineWave};
-use sdl2::Sdl;
-use sdl2::pixels::Color;
-use std::{num::Wrapping, time::SystemTime};
-use sdl2::rect::Point;
-use sdl2::keyboard::Keycode;
-use sdl2::event::Event;
-use rand::Rng;
-
-#[derive(PartialEq)]
-enum ShiftTypes {
-    AsX,
-}
-
-const SHIFT_TYPE: ShiftTypes = ShiftTypes::AsX;
-
-const BACKGROUND_COLOR: Color = Color::RGB(0, 0, 0);
-const DRAW_COLOR: Color = Color::RGB(255, 255, 255);
+use crate::font::Font;
+use crate::framebuffer::{Framebuffer, PLANE_COUNT};
+use crate::frontend::{Beeper, Hotkey, Keypad, Screen};
+use crate::instruction::Instruction;
+use crate::palette::Palette;
+use crate::savestate::MachineState;
+use crate::quirks::{JumpTypes, LoadStoreTypes, Quirks, ShiftTypes};
+use std::{fmt, num::Wrapping};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-static SCAN_CODES: [Scancode; 16] = [Scancode::X, Scancode::Num1, Scancode::Num2, Scancode::Num3, 
-                                  Scancode::Q, Scancode::W, Scancode::E, Scancode::A, 
-                                  Scancode::S, Scancode::D, Scancode::Z, Scancode::C, 
-                                  Scancode::Num4, Scancode::R, Scancode::F, Scancode::V];
-
-pub struct CPU<'a> {
-    memory: [u8; 4096],
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU {
+    memory: Vec<u8>,
     vx: [Wrapping<u8>; 16],
     stack_register: Vec<u16>,
     pc: u16,
     index_register: u16,
     delay_timer: u8,
     sound_timer: u8,
-    display: &'a mut Canvas<sdl2::video::Window>,
-    audio_device: AudioDevice<SineWave>,
-    pub event: sdl2::EventPump,
-    event_system: sdl2::EventSubsystem,
-    display_array: [u64; 32],
-    current_time: SystemTime,
+    screen: Box<dyn Screen>,
+    beeper: Box<dyn Beeper>,
+    keypad: Box<dyn Keypad>,
+    display_array: Framebuffer,
+    rng: StdRng,
+    quirks: Quirks,
+    /// Set by each 60 Hz tick and consumed by DXYN when `quirks.display_wait` is on.
+    vblank: bool,
+    /// SUPER-CHIP RPL user flags, saved and restored by FX75 and FX85.
+    rpl_flags: [u8; 16],
+    /// Set by 00FD; a halted CPU no longer executes instructions.
+    halted: bool,
+    /// XO-CHIP 1-bit audio pattern loaded by F002, played instead of the plain tone.
+    audio_pattern: Option<[u8; 16]>,
+    /// XO-CHIP playback pitch set by FX3A.
+    pitch: u8,
+    /// Progress of an FX0A that is waiting for a key.
+    key_wait: Option<KeyWait>,
+    /// Addresses written by the most recent `step`, for debugger watchpoints.
+    memory_writes: Vec<u16>,
+    /// Address and opcode of the instruction being executed, for error reports.
+    current_instruction: (u16, u16),
+    /// Where FX30 finds the big font.
+    big_font_address: u16,
 }
 
-impl CPU<'_> {
-    pub fn new(canvas: &mut Canvas<sdl2::video::Window>, device: AudioDevice<SineWave>, sdl_context: Sdl) -> CPU {
-        let mut ram: [u8; 4096] = [0; 4096];
-        for i in 0..font::FONT.len() {
-            ram[0x050 + i] = FONT[i];
+/// FX0A completes on a press followed by a release, like the COSMAC VIP.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum KeyWait {
+    /// Waiting for a press. Keys in the `held` bitmask were already down when the wait began and
+    /// only count once they have been released and pressed again.
+    Press { held: u16 },
+    /// Waiting for this key to be released.
+    Release(u8),
+}
+
+/// A fault that stops the emulated program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmuError {
+    /// The ROM does not fit between 0x200 and the end of memory.
+    RomTooLarge { size: usize },
+    /// 00EE with nothing on the stack.
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// 2NNN with the stack already full.
+    StackOverflow { pc: u16, opcode: u16 },
+    /// The program counter ran off the end of memory.
+    PcOutOfRange { pc: u16 },
+    /// An instruction read or wrote past the end of memory; `address` is the first one outside it.
+    MemoryOutOfBounds { pc: u16, opcode: u16, address: usize },
+    UnknownOpcode { pc: u16, opcode: u16 },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmuError::RomTooLarge { size } => write!(f, "ROM is too large ({} bytes)", size),
+            EmuError::StackUnderflow { pc, opcode } => write!(f, "stack underflow at {:#05X} ({:04X})", pc, opcode),
+            EmuError::StackOverflow { pc, opcode } => write!(f, "stack overflow at {:#05X} ({:04X})", pc, opcode),
+            EmuError::PcOutOfRange { pc } => write!(f, "program counter out of range at {:#05X}", pc),
+            EmuError::MemoryOutOfBounds { pc, opcode, address } => {
+                write!(f, "memory access out of bounds at {:#05X} ({:04X}): address {:#X}", pc, opcode, address)
+            },
+            EmuError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode {:04X} at {:#05X}", opcode, pc),
         }
-        CPU {
-            memory: ram,
+    }
+}
+
+/// Room for XO-CHIP's 64K address space. Other presets only address the first 4K of it, as
+/// `quirks.memory_size` says.
+const MEMORY_SIZE: usize = 0x10000;
+
+/// Where the COSMAC VIP interpreter kept its call stack.
+const STACK_ADDRESS: usize = 0xEA0;
+
+const FONT_ADDRESS: usize = 0x050;
+/// Where the big font goes unless `set_font` moves it, right after the small font.
+pub const BIG_FONT_ADDRESS: u16 = 0x0A0;
+
+impl CPU {
+    pub fn new(screen: Box<dyn Screen>, beeper: Box<dyn Beeper>, keypad: Box<dyn Keypad>) -> CPU {
+        let mut cpu = CPU {
+            memory: vec![0; MEMORY_SIZE],
             vx: [Wrapping(0); 16],
-            stack_register: vec![],
+            stack_register: Vec::with_capacity(Quirks::default().stack_depth),
             pc: 0x200,
             index_register: 0,
             delay_timer: 0,
             sound_timer: 0,
-            display: canvas,
-            audio_device: device,
-            event: sdl_context.event_pump().unwrap(),
-            event_system: sdl_context.event().unwrap(),
-            display_array: [0; 32],
-            current_time: SystemTime::now(),
+            screen,
+            beeper,
+            keypad,
+            display_array: Framebuffer::new(),
+            rng: StdRng::from_entropy(),
+            quirks: Quirks::default(),
+            vblank: false,
+            rpl_flags: [0; 16],
+            halted: false,
+            audio_pattern: None,
+            pitch: 64,
+            key_wait: None,
+            memory_writes: Vec::new(),
+            current_instruction: (0x200, 0),
+            big_font_address: BIG_FONT_ADDRESS,
+        };
+        cpu.set_font(&Font::default(), BIG_FONT_ADDRESS);
+        cpu
+    }
+
+    /// Replaces the font in memory, putting the big font at `big_font_address`.
+    pub fn set_font(&mut self, font: &Font, big_font_address: u16) {
+        let old = self.big_font_address as usize;
+        self.memory[old..old + font.big.len()].fill(0);
+        self.memory[FONT_ADDRESS..FONT_ADDRESS + font.small.len()].copy_from_slice(&font.small);
+        let big = big_font_address as usize;
+        self.memory[big..big + font.big.len()].copy_from_slice(&font.big);
+        self.big_font_address = big_font_address;
+    }
+
+    pub fn load(&mut self, data: Vec<u8>) -> Result<(), EmuError> {
+        if 0x200 + data.len() > self.quirks.memory_size {
+            return Err(EmuError::RomTooLarge { size: data.len() });
         }
+        self.memory[0x200..0x200 + data.len()].copy_from_slice(&data);
+        Ok(())
     }
 
-    pub fn load(&mut self, data: Vec<u8>) {
-        for i in 0..data.len() {
-            if 0x200 + i > 4095 {
-                panic!("ROM too large!");
-            }
-            self.memory[0x200 + i] = *data.get(i).unwrap();
+    /// Reseeds the generator behind CXNN so runs are reproducible.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_palette(&mut self, palette: &Palette) {
+        self.screen.set_palette(palette);
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        self.screen.toggle_fullscreen();
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.beeper.set_muted(muted);
+    }
+
+    /// Hands the framebuffer to the screen. Frontends call this once per frame, not per instruction.
+    pub fn draw(&mut self) {
+        self.screen.draw(&self.display_array);
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display_array
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn registers(&self) -> [u8; 16] {
+        self.vx.map(|register| register.0)
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        &self.stack_register
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.quirks.stack_depth
+    }
+
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay_timer, self.sound_timer)
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    pub fn memory_writes(&self) -> &[u16] {
+        &self.memory_writes
+    }
+
+    /// Where the instruction executed last, the one that made `memory_writes`, was fetched from.
+    pub fn last_instruction_address(&self) -> u16 {
+        self.current_instruction.0
+    }
+
+    /// The instruction at the program counter, without executing it.
+    pub fn peek_instruction(&self) -> u16 {
+        let pc = self.pc as usize;
+        ((self.memory[pc] as u16) << 8) | (self.memory[(pc + 1) % MEMORY_SIZE] as u16)
+    }
+
+    /// Processes pending frontend input. Returns `false` once the user has asked to quit.
+    pub fn poll_input(&mut self) -> bool {
+        self.keypad.update()
+    }
+
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        self.keypad.take_hotkeys()
+    }
+
+    pub fn is_rewind_held(&self) -> bool {
+        self.keypad.is_rewind_held()
+    }
+
+    pub fn save_state(&self) -> MachineState {
+        MachineState {
+            memory: self.memory.clone(),
+            vx: self.vx.map(|register| register.0),
+            stack_register: self.stack_register.clone(),
+            pc: self.pc,
+            index_register: self.index_register,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            display_array: self.display_array.clone(),
+            vblank: self.vblank,
+            rpl_flags: self.rpl_flags,
+            halted: self.halted,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
         }
     }
 
+    pub fn restore_state(&mut self, state: &MachineState) {
+        self.memory = state.memory.clone();
+        self.memory.resize(MEMORY_SIZE, 0);
+        self.vx = state.vx.map(Wrapping);
+        self.stack_register = state.stack_register.clone();
+        self.pc = state.pc;
+        self.index_register = state.index_register;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.display_array = state.display_array.clone();
+        self.vblank = state.vblank;
+        self.rpl_flags = state.rpl_flags;
+        self.halted = state.halted;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.key_wait = None;
+        self.beeper.set_pattern(self.audio_pattern, self.pitch);
+    }
     pub fn update_timers(&mut self) {
+        self.vblank = true;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -81,434 +283,970 @@ impl CPU<'_> {
         }
     }
 
-    fn fetch(&mut self) -> u16 {
-        let instruction = ((self.memory[self.pc as usize] as u16) << 8) | (self.memory[self.pc as usize + 1] as u16);
-        self.pc += 2;
-        instruction
+    fn fetch(&mut self) -> Result<u16, EmuError> {
+        if self.pc as usize + 1 >= self.quirks.memory_size {
+            return Err(EmuError::PcOutOfRange { pc: self.pc });
+        }
+        let instruction = self.peek_instruction();
+        self.current_instruction = (self.pc, instruction);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(instruction)
     }
-    fn execute(&mut self, instruction: u16) {
-        match (instruction & 0xF000) >> 12 {
-            0x0 => match instruction {
-                0x00E0 => self.clear_screen(),
-                0x00EE => self.stack_return(),
-                _ => println!("unknow 0 instruction {:#06x}", instruction)
-            },
-            0x1 => self.jump(instruction),
-            0x2 => self.call_subroutine(instruction),
-            0x3 => self.jump_if_val_is_equal(instruction),
-            0x4 => self.jump_if_val_not_equal(instruction),
-            0x5 => self.jump_if_reg_is_equal(instruction),
-            0x6 => self.set_as_value(instruction),
-            0x7 => self.add_as_value(instruction),
-            0x8 => match instruction & 0x000F {
-                0x0000 => self.set_as_register(instruction),
-                0x0001 => self.or_register(instruction),
-                0x0002 => self.and_register(instruction),
-                0x0003 => self.xor_register(instruction),
-                0x0004 => self.add_as_register(instruction),
-                0x0005 => self.sub_vx_xy(instruction),
-                0x0006 => self.shift_register_right(instruction),
-                0x0007 => self.sub_vy_xx(instruction),
-                0x000E => self.shift_register_left(instruction),
-                _ => println!("unknow 8 instruction {:#06x}", instruction)
-            },
-            0x9 => self.jump_if_reg_not_equal(instruction),
-            0xA => self.set_index(instruction),
-            0xC => self.random(instruction),
-            0xD => self.display_sprite(instruction),
-            0xE => match instruction & 0x00FF {
-                0x009E => self.skip_if_key(instruction),
-                0x00A1 => self.skip_if_not_key(instruction),
-                _ => println!("unknow 0 instruction {:#06x}", instruction)
+    fn execute(&mut self, opcode: u16) -> Result<(), EmuError> {
+        match Instruction::decode(opcode) {
+            Instruction::ScrollDown(n) => self.scroll_down(n),
+            Instruction::ScrollUp(n) => self.scroll_up(n),
+            Instruction::ClearScreen => self.clear_screen(),
+            Instruction::Return => self.stack_return()?,
+            Instruction::ScrollRight => self.scroll_right(),
+            Instruction::ScrollLeft => self.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::LowRes => self.set_resolution(false),
+            Instruction::HighRes => self.set_resolution(true),
+            Instruction::Jump(nnn) => self.jump(nnn),
+            Instruction::Call(nnn) => self.call_subroutine(nnn)?,
+            Instruction::SkipIfEqualValue(x, nn) => self.jump_if_val_is_equal(x, nn),
+            Instruction::SkipIfNotEqualValue(x, nn) => self.jump_if_val_not_equal(x, nn),
+            Instruction::SkipIfEqualRegister(x, y) => self.jump_if_reg_is_equal(x, y),
+            Instruction::SaveRange(x, y) => self.save_register_range(x, y)?,
+            Instruction::LoadRange(x, y) => self.load_register_range(x, y)?,
+            Instruction::SetValue(x, nn) => self.set_as_value(x, nn),
+            Instruction::AddValue(x, nn) => self.add_as_value(x, nn),
+            Instruction::SetRegister(x, y) => self.set_as_register(x, y),
+            Instruction::Or(x, y) => self.or_register(x, y),
+            Instruction::And(x, y) => self.and_register(x, y),
+            Instruction::Xor(x, y) => self.xor_register(x, y),
+            Instruction::AddRegister(x, y) => self.add_as_register(x, y),
+            Instruction::Subtract(x, y) => self.sub_vx_xy(x, y),
+            Instruction::ShiftRight(x, y) => self.shift_register_right(x, y),
+            Instruction::SubtractReversed(x, y) => self.sub_vy_xx(x, y),
+            Instruction::ShiftLeft(x, y) => self.shift_register_left(x, y),
+            Instruction::SkipIfNotEqualRegister(x, y) => self.jump_if_reg_not_equal(x, y),
+            Instruction::SetIndex(nnn) => self.set_index(nnn),
+            Instruction::JumpWithOffset(nnn, x) => self.jump_with_offset(nnn, x),
+            Instruction::Random(x, nn) => self.random(x, nn),
+            Instruction::Draw(x, y, n) => self.display_sprite(x, y, n)?,
+            Instruction::SkipIfKey(x) => self.skip_if_key(x),
+            Instruction::SkipIfNotKey(x) => self.skip_if_not_key(x),
+            Instruction::SetIndexLong => self.set_index_long()?,
+            Instruction::SelectPlanes(mask) => self.select_planes(mask),
+            Instruction::LoadAudioPattern => self.load_audio_pattern()?,
+            Instruction::GetDelayTimer(x) => self.get_delay_timer(x),
+            Instruction::WaitKey(x) => self.get_key(x),
+            Instruction::SetDelayTimer(x) => self.set_delay_timer(x),
+            Instruction::SetSoundTimer(x) => self.set_sound_timer(x),
+            Instruction::AddToIndex(x) => self.add_to_index(x),
+            Instruction::Font(x) => self.get_font_character(x),
+            Instruction::BigFont(x) => self.get_big_font_character(x),
+            Instruction::BinaryToDecimal(x) => self.binary_to_decimal(x)?,
+            Instruction::SetPitch(x) => self.set_pitch(x),
+            Instruction::SaveRegisters(x) => self.save_registers(x)?,
+            Instruction::LoadRegisters(x) => self.load_registers(x)?,
+            Instruction::SaveFlags(x) => self.save_flags(x),
+            Instruction::LoadFlags(x) => self.load_flags(x),
+            Instruction::Unknown(opcode) => {
+                let (pc, _) = self.current_instruction;
+                return Err(EmuError::UnknownOpcode { pc, opcode });
             },
-            0xF => match instruction & 0x00FF {
-                0x0007 => self.get_delay_timer(instruction),
-                0x0015 => self.set_delay_timer(instruction),
-                0x0018 => self.set_sound_timer(instruction),
-                0x001E => self.add_to_index(instruction),
-                0x000A => self.get_key(instruction),
-                0x0029 => self.get_font_character(instruction),
-                0x0033 => self.binary_to_decimal(instruction),
-                0x0055 => self.save_registers(instruction),
-                0x0065 => self.load_registers(instruction),
-                _ => println!("unknow F instruction {:#06x}", instruction)
-            },
-
-            _ => println!("unknown instruction {:#06x}", instruction)
         }
+        Ok(())
     }
 
-    fn get_pixel(&mut self, x: u32, y: u32) -> u8 {
-        ((self.display_array[y as usize] & (1 << x)) >> x) as u8
+    fn is_key_down(&mut self, key: u8) -> bool {
+        self.keypad.is_key_down(key)
     }
 
-    fn set_pixel(&mut self, x: u32, y: u32, bit: u8) {
-        if bit == 1 {
-            self.display_array[y as usize] = self.display_array[y as usize] | (1 << x);
+    /// Executes exactly one instruction without touching the timers.
+    ///
+    /// On a fault the program counter is left on the faulting instruction, so stepping again
+    /// reports the same error.
+    pub fn step(&mut self) -> Result<(), EmuError> {
+        if self.halted {
+            return Ok(());
         }
-        if bit == 0  {
-            self.display_array[y as usize] = self.display_array[y as usize] & !(1 << x);
+        self.memory_writes.clear();
+        let pc = self.pc;
+        let result = self.fetch().and_then(|instruction| self.execute(instruction));
+        if result.is_err() {
+            self.pc = pc;
         }
+        result
     }
 
-    fn is_key_down(&mut self, key: u8) -> bool {
-        self.event.keyboard_state().is_scancode_pressed(SCAN_CODES[key as usize])
+    /// Executes `cycles` instructions and ticks the timers once, i.e. one 60 Hz frame.
+    pub fn run_frame(&mut self, cycles: u32) -> Result<&Framebuffer, EmuError> {
+        for _ in 0..cycles {
+            self.step()?;
+        }
+        self.end_frame();
+        Ok(&self.display_array)
     }
 
-    fn update_display(&mut self) {
-        self.display.set_draw_color(BACKGROUND_COLOR);
-        self.display.clear();
-        for row in 0..32 {
-            for column in 0..64 {
-                if (self.display_array[row] & (1 << column)) != 0 {
-                    self.display.set_draw_color(DRAW_COLOR);
-                    self.display.draw_point(Point::new(column, row as i32)).expect("Failed to draw point");
-                }
-            }
-        }
-        self.display.present();
+    /// Ticks the timers at the end of a frame whose instructions were stepped one by one, and
+    /// plays the frame's sound: the tone if the sound timer was running through it.
+    pub fn end_frame(&mut self) {
+        let beeping = self.sound_timer > 0;
+        self.update_timers();
+        self.beeper.play_frame(beeping);
     }
+}
 
-    pub fn run(&mut self) {
-        if self.current_time.elapsed().unwrap().as_millis() >= 17 {
-            self.update_timers();
-            self.current_time = SystemTime::now();
-        }
-        let instruction = self.fetch();
-        //println!("Progam Counter: {:#06x} Instruction: {:#06x} Sound Timer: {}, Delay Timer: {}", self.pc, instruction, self.sound_timer, self.delay_timer);
-        self.execute(instruction);
-        if self.sound_timer > 0 {
-            self.audio_device.resume();
-        } else {
-            self.audio_device.pause();
+impl CPU {
+    fn  clear_screen (&mut self) {
+        self.display_array.clear();
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.display_array.scroll_down(n as usize);
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.display_array.scroll_up(n as usize);
+    }
+
+    fn scroll_right(&mut self) {
+        self.display_array.scroll_right(4);
+    }
+
+    fn scroll_left(&mut self) {
+        self.display_array.scroll_left(4);
+    }
+
+    fn exit(&mut self) {
+        self.halted = true;
+    }
+
+    fn set_resolution(&mut self, hires: bool) {
+        self.display_array.set_hires(hires);
+    }
+
+    /// Fails unless the `length` bytes starting at `address` are all inside addressable memory.
+    fn check_memory(&self, address: usize, length: usize) -> Result<(), EmuError> {
+        let memory_size = self.quirks.memory_size;
+        if address + length > memory_size {
+            let (pc, opcode) = self.current_instruction;
+            return Err(EmuError::MemoryOutOfBounds { pc, opcode, address: address.max(memory_size) });
         }
+        Ok(())
     }
 
-    
-}
+    fn write_memory(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+        self.memory_writes.push(address as u16);
+    }
 
-impl CPU<'_> {
-    fn  clear_screen (&mut self) {
-        self.display_array = [0; 32];
-        self.display.set_draw_color(BACKGROUND_COLOR);
-        self.display.clear();
-        self.display.present();
+    fn jump(&mut self, nnn: u16) {
+        self.pc = nnn;
     }
 
-    fn jump(&mut self, instruction: u16) {
-        self.pc = instruction & 0x0FFF;
+    fn jump_with_offset(&mut self, nnn: u16, x: u8) {
+        let offset = match self.quirks.jump {
+            JumpTypes::V0 => self.vx[0].0,
+            JumpTypes::VX => self.vx[x as usize].0,
+        };
+        self.pc = (nnn) + offset as u16;
     }
 
-    fn set_as_value(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping((instruction & 0x00FF) as u8);
+    fn set_index_long(&mut self) -> Result<(), EmuError> {
+        self.check_memory(self.pc as usize, 2)?;
+        self.index_register = ((self.memory[self.pc as usize] as u16) << 8) | (self.memory[self.pc as usize + 1] as u16);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
-    fn add_as_value(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = self.vx[((instruction & 0x0F00) >> 8) as usize] + Wrapping((instruction & 0x00FF) as u8);
+    fn set_as_value(&mut self, x: u8, nn: u8) {
+        self.vx[x as usize] = Wrapping(nn);
     }
 
-    fn set_index(&mut self, instruction: u16) {
-        self.index_register = instruction & 0x0FFF;
+    fn add_as_value(&mut self, x: u8, nn: u8) {
+        self.vx[x as usize] += Wrapping(nn);
     }
 
-    fn display_sprite(&mut self, instruction: u16) {
-        let x = self.vx[((instruction & 0x0F00) >> 8) as usize].0 % 64;
-        let y = self.vx[((instruction & 0x00F0) >> 4) as usize].0 % 32;
-        self.vx[0xF as usize] = Wrapping(0);
-        let n = instruction & 0x000F;
+    fn set_index(&mut self, nnn: u16) {
+        self.index_register = nnn;
+    }
 
-        for byte in 0..n {
-            let row = self.memory[(self.index_register + byte) as usize];
+    fn display_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), EmuError> {
+        if self.quirks.display_wait {
+            if !self.vblank {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(());
+            }
+            self.vblank = false;
+        }
+        let x = self.vx[x as usize].0 as usize;
+        let y = self.vx[y as usize].0 as usize;
+        // DXY0 draws a 16x16 SUPER-CHIP sprite, two bytes per row.
+        let (sprite_width, sprite_height) = if n == 0 { (16, 16) } else { (8, n as u16) };
+        let sprite_size = sprite_height as usize * sprite_width / 8;
 
-            for bit in 0..8 {
-                let cx = x + bit;
-                if cx > 63 {
-                    break;
-                }
-                let cy = y as u16 + byte;
-                if cy > 31 {
-                    break;
-                }
-                let cpixel = self.get_pixel(cx as u32, cy as u32);
-                let row_pixel = (row & (1 << 7 - bit)) >> 7 - bit;
-                self.set_pixel(cx as u32, cy as u32, cpixel ^ row_pixel);
+        // With both XO-CHIP planes selected, the second plane's sprite follows the first one's.
+        let planes: Vec<usize> = (0..PLANE_COUNT).filter(|plane| self.display_array.selected_planes() & (1 << plane) != 0).collect();
+        let addresses: Vec<usize> = (0..planes.len()).map(|i| self.index_register as usize + i * sprite_size).collect();
+        // Check every plane's sprite before drawing any, so a fault leaves the screen untouched.
+        for &address in &addresses {
+            self.check_memory(address, sprite_size)?;
+        }
+        self.vx[0xF] = Wrapping(0);
+        for (plane, address) in planes.into_iter().zip(addresses) {
+            if self.draw_sprite_plane(plane, address, x, y, sprite_width, sprite_height) {
+                self.vx[0xF] = Wrapping(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws one plane of a sprite. Returns `true` if any lit pixel was erased.
+    fn draw_sprite_plane(&mut self, plane: usize, address: usize, x: usize, y: usize, sprite_width: usize, sprite_height: u16) -> bool {
+        let width = self.display_array.width();
+        let height = self.display_array.height();
+        let x = x % width;
+        let y = y % height;
+        let mut collision = false;
+
+        for byte in 0..sprite_height {
+            let row = if sprite_width == 16 {
+                let address = address + byte as usize * 2;
+                ((self.memory[address] as u16) << 8) | self.memory[address + 1] as u16
+            } else {
+                (self.memory[address + byte as usize] as u16) << 8
+            };
 
-                if cpixel == 1 && row_pixel == 1 {
-                    self.vx[0xF as usize] = Wrapping(1);
+            for bit in 0..sprite_width {
+                let mut cx = x + bit;
+                let mut cy = y + byte as usize;
+                if self.quirks.clip_sprites {
+                    if cx >= width || cy >= height {
+                        break;
+                    }
+                } else {
+                    cx %= width;
+                    cy %= height;
+                }
+                if row & (0x8000 >> bit) != 0 && self.display_array.flip(plane, cx, cy) {
+                    collision = true;
                 }
             }
 
         }
 
-        self.update_display();
+        collision
+    }
+
+    /// Memory address of stack entry `depth` when `quirks.stack_in_memory` is on.
+    fn stack_slot(depth: usize) -> usize {
+        STACK_ADDRESS + depth * 2
     }
 
-    fn call_subroutine(&mut self, instruction: u16) {
+    fn call_subroutine(&mut self, nnn: u16) -> Result<(), EmuError> {
+        let depth = self.stack_register.len();
+        if depth >= self.quirks.stack_depth {
+            let (pc, opcode) = self.current_instruction;
+            return Err(EmuError::StackOverflow { pc, opcode });
+        }
+        if self.quirks.stack_in_memory {
+            let slot = CPU::stack_slot(depth);
+            self.check_memory(slot, 2)?;
+            let [high, low] = self.pc.to_be_bytes();
+            self.write_memory(slot, high);
+            self.write_memory(slot + 1, low);
+        }
         self.stack_register.push(self.pc);
-        self.pc = instruction & (0x0FFF);
+        self.pc = nnn;
+        Ok(())
     }
 
-    fn stack_return(&mut self) {
-        self.pc = match self.stack_register.pop() {
+    fn stack_return(&mut self) -> Result<(), EmuError> {
+        let address = match self.stack_register.pop() {
             Some(address) => address,
-            None => panic!("called return with empty stack"),
+            None => {
+                let (pc, opcode) = self.current_instruction;
+                return Err(EmuError::StackUnderflow { pc, opcode });
+            },
+        };
+        // On the VIP a ROM could overwrite its own return addresses, so memory wins.
+        self.pc = if self.quirks.stack_in_memory {
+            let slot = CPU::stack_slot(self.stack_register.len());
+            u16::from_be_bytes([self.memory[slot], self.memory[slot + 1]])
+        } else {
+            address
         };
+        Ok(())
+    }
+
+    /// Skips the next instruction, which is four bytes long if it is XO-CHIP's F000 NNNN.
+    fn skip_next(&mut self) {
+        self.pc = self.pc.wrapping_add(Instruction::decode(self.peek_instruction()).size());
     }
 
-    fn jump_if_val_is_equal(&mut self, instruction: u16) {
-        if self.vx[((instruction & 0x0F00) >> 8) as usize].0 == (instruction & 0x00FF) as u8 {
-            self.pc += 2;
+    fn jump_if_val_is_equal(&mut self, x: u8, nn: u8) {
+        if self.vx[x as usize].0 == nn {
+            self.skip_next();
         }
     }
 
-    fn jump_if_val_not_equal(&mut self, instruction: u16) {
-        if self.vx[((instruction & 0x0F00) >> 8) as usize].0 != (instruction & 0x00FF) as u8 {
-            self.pc += 2;
+    fn jump_if_val_not_equal(&mut self, x: u8, nn: u8) {
+        if self.vx[x as usize].0 != nn {
+            self.skip_next();
         }
     }
 
-    fn jump_if_reg_is_equal(&mut self, instruction: u16) {
-        if self.vx[((instruction & 0x0F00) >> 8) as usize] == self.vx[((instruction & 0x00F0) >> 4) as usize] {
-            self.pc += 2;
+    fn jump_if_reg_is_equal(&mut self, x: u8, y: u8) {
+        if self.vx[x as usize] == self.vx[y as usize] {
+            self.skip_next();
         }
     }
 
-    fn jump_if_reg_not_equal(&mut self, instruction: u16) {
-        if self.vx[((instruction & 0x0F00) >> 8) as usize] != self.vx[((instruction & 0x00F0) >> 4) as usize] {
-            self.pc += 2;
+    fn jump_if_reg_not_equal(&mut self, x: u8, y: u8) {
+        if self.vx[x as usize] != self.vx[y as usize] {
+            self.skip_next();
         }
     }
 
-    fn set_as_register(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = self.vx[((instruction & 0x00F0) >> 4) as usize];
+    fn set_as_register(&mut self, x: u8, y: u8) {
+        self.vx[x as usize] = self.vx[y as usize];
     }
 
-    fn or_register(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = self.vx[((instruction & 0x0F00) >> 8) as usize] | self.vx[((instruction & 0x00F0) >> 4) as usize];
+    fn or_register(&mut self, x: u8, y: u8) {
+        self.vx[x as usize] |= self.vx[y as usize];
+        if self.quirks.vf_reset {
+            self.vx[0xF] = Wrapping(0);
+        }
     }
 
-    fn and_register(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = self.vx[((instruction & 0x0F00) >> 8) as usize] & self.vx[((instruction & 0x00F0) >> 4) as usize];
+    fn and_register(&mut self, x: u8, y: u8) {
+        self.vx[x as usize] &= self.vx[y as usize];
+        if self.quirks.vf_reset {
+            self.vx[0xF] = Wrapping(0);
+        }
     }
 
-    fn xor_register(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = self.vx[((instruction & 0x0F00) >> 8) as usize] ^ self.vx[((instruction & 0x00F0) >> 4) as usize];
+    fn xor_register(&mut self, x: u8, y: u8) {
+        self.vx[x as usize] ^= self.vx[y as usize];
+        if self.quirks.vf_reset {
+            self.vx[0xF] = Wrapping(0);
+        }
     }
 
-    fn add_as_register(&mut self, instruction: u16) {
-        let x = self.vx[((instruction & 0x0F00) >> 8) as usize];
-        let y = self.vx[((instruction & 0x00F0) >> 4) as usize];
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = x + y;
-        if (x.0 as u16 + y.0 as u16) > u8::MAX as u16 {
-            self.vx[0xF as usize] = Wrapping(1);
+    fn add_as_register(&mut self, x: u8, y: u8) {
+        let vx = self.vx[x as usize];
+        let vy = self.vx[y as usize];
+        self.vx[x as usize] = vx + vy;
+        if (vx.0 as u16 + vy.0 as u16) > u8::MAX as u16 {
+            self.vx[0xF] = Wrapping(1);
         } else {
-            self.vx[0xF as usize] = Wrapping(0);
+            self.vx[0xF] = Wrapping(0);
         }
     }
 
-    fn sub_vx_xy(&mut self, instruction: u16) {
-        let x = self.vx[((instruction & 0x0F00) >> 8) as usize];
-        let y = self.vx[((instruction & 0x00F0) >> 4) as usize];
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = x - y;
-        if x >= y {
-            self.vx[0xF as usize] = Wrapping(1);
+    fn sub_vx_xy(&mut self, x: u8, y: u8) {
+        let vx = self.vx[x as usize];
+        let vy = self.vx[y as usize];
+        self.vx[x as usize] = vx - vy;
+        if vx >= vy {
+            self.vx[0xF] = Wrapping(1);
         } else {
-            self.vx[0xF as usize] = Wrapping(0);
+            self.vx[0xF] = Wrapping(0);
         }
     }
 
-    fn sub_vy_xx(&mut self, instruction: u16) {
-        let x = self.vx[((instruction & 0x0F00) >> 8) as usize];
-        let y = self.vx[((instruction & 0x00F0) >> 4) as usize];
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = y - x;
-        if y >= x {
-            self.vx[0xF as usize] = Wrapping(1);
+    fn sub_vy_xx(&mut self, x: u8, y: u8) {
+        let vx = self.vx[x as usize];
+        let vy = self.vx[y as usize];
+        self.vx[x as usize] = vy - vx;
+        if vy >= vx {
+            self.vx[0xF] = Wrapping(1);
         } else {
-            self.vx[0xF as usize] = Wrapping(0);
+            self.vx[0xF] = Wrapping(0);
         }
     }
 
-    fn shift_register_right(&mut self, instruction: u16) {
-        if SHIFT_TYPE == ShiftTypes::AsX {
-            let x = self.vx[((instruction & 0x0F00) >> 8) as usize].0;
-            self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(x >> 1);
-            if x & 1 == 1 {
-                self.vx[0xF as usize] = Wrapping(1);
-            } else {
-                self.vx[0xF as usize] = Wrapping(0);
-            }
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        match self.quirks.shift {
+            ShiftTypes::AsX => self.vx[x as usize].0,
+            ShiftTypes::AsY => self.vx[y as usize].0,
+        }
+    }
+
+    fn shift_register_right(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.vx[x as usize] = Wrapping(value >> 1);
+        if value & 1 == 1 {
+            self.vx[0xF] = Wrapping(1);
         } else {
-            let x = self.vx[((instruction & 0x00F0) >> 4) as usize].0;
-            self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(x >> 1);
-            if x & 1 == 1 {
-                self.vx[0xF as usize] = Wrapping(1);
-            } else {
-                self.vx[0xF as usize] = Wrapping(0);
-            }
+            self.vx[0xF] = Wrapping(0);
         }
     }
 
-    fn shift_register_left(&mut self, instruction: u16) {
-        if SHIFT_TYPE == ShiftTypes::AsX {
-            let x = self.vx[((instruction & 0x0F00) >> 8) as usize].0;
-            self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(x << 1);
-            if x & (1 << 7) == 128 {
-                self.vx[0xF as usize] = Wrapping(1);
-            } else {
-                self.vx[0xF as usize] = Wrapping(0);
-            }
-            
+    fn shift_register_left(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.vx[x as usize] = Wrapping(value << 1);
+        if value & (1 << 7) == 128 {
+            self.vx[0xF] = Wrapping(1);
         } else {
-            let x = self.vx[((instruction & 0x00F0) >> 4) as usize].0;
-            self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(x << 1);
-            if x & (1 << 7) == 128 {
-                self.vx[0xF as usize] = Wrapping(1);
-            } else {
-                self.vx[0xF as usize] = Wrapping(0);
-            }
+            self.vx[0xF] = Wrapping(0);
         }
     }
 
-    fn random(&mut self, instruction: u16) {
-        let random_number: u8 = rand::thread_rng().gen();
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping((instruction & 0x00FF) as u8 & random_number);
+    fn random(&mut self, x: u8, nn: u8) {
+        let random_number: u8 = self.rng.gen();
+        self.vx[x as usize] = Wrapping(nn & random_number);
     }
 
-    fn get_delay_timer(&mut self, instruction: u16) {
-        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(self.delay_timer);
+    fn get_delay_timer(&mut self, x: u8) {
+        self.vx[x as usize] = Wrapping(self.delay_timer);
     }
 
-    fn set_delay_timer(&mut self, instruction: u16) {
-        self.delay_timer = self.vx[((instruction & 0x0F00) >> 8) as usize].0;
+    fn set_delay_timer(&mut self, x: u8) {
+        self.delay_timer = self.vx[x as usize].0;
     }
 
-    fn set_sound_timer(&mut self, instruction: u16) {
-        self.sound_timer = self.vx[((instruction & 0x0F00) >> 8) as usize].0;
+    fn set_sound_timer(&mut self, x: u8) {
+        self.sound_timer = self.vx[x as usize].0;
     }
 
-    fn add_to_index(&mut self, instruction: u16) {
-        self.index_register += self.vx[((instruction & 0x0F00) >> 8) as usize].0 as u16;
+    fn add_to_index(&mut self, x: u8) {
+        self.index_register = self.index_register.wrapping_add(self.vx[x as usize].0 as u16);
     }
 
-    fn get_key(&mut self, instruction: u16) {
-        'wait_key: loop {
-            if self.current_time.elapsed().unwrap().as_millis() >= 17 {
-                self.update_timers();
-                self.current_time = SystemTime::now();
-            }
-            for event in self.event.poll_iter() {
-                match event {
-                    Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                        self.event_system.push_event(Event::Quit { timestamp: 0 }).unwrap();
-                        break 'wait_key;
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Num1), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x1);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Num2), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x2);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Num3), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x3);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Num4), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xC);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Q), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x4);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::W), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x5);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::E), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x6);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::R), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xD);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::A), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x7);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::S), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x8);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::D), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x9);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::F), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xE);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::Z), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xA);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::X), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0x0);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::C), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xB);
-                        break 'wait_key
-                    },
-                    Event::KeyUp { keycode: Some(Keycode::V), .. } => {
-                        self.vx[((instruction & 0x0F00) >> 8) as usize] = Wrapping(0xF);
-                        break 'wait_key
-                    },
-                    _ => {}
+    fn keys_down(&self) -> u16 {
+        (0..16).filter(|&key| self.keypad.is_key_down(key)).fold(0, |keys, key| keys | 1 << key)
+    }
+
+    fn get_key(&mut self, x: u8) {
+        let keys_down = self.keys_down();
+        self.key_wait = match self.key_wait.unwrap_or(KeyWait::Press { held: keys_down }) {
+            KeyWait::Press { held } => {
+                let pressed = keys_down & !held;
+                if pressed != 0 {
+                    Some(KeyWait::Release(pressed.trailing_zeros() as u8))
+                } else {
+                    Some(KeyWait::Press { held: held & keys_down })
                 }
-            }
+            },
+            KeyWait::Release(key) if keys_down & (1 << key) == 0 => {
+                self.vx[x as usize] = Wrapping(key);
+                None
+            },
+            waiting => Some(waiting),
+        };
+        // Re-execute this instruction until the wait is over, so the frontend keeps running.
+        if self.key_wait.is_some() {
+            self.pc = self.pc.wrapping_sub(2);
         }
-        
     }
 
-    fn get_font_character(&mut self, instruction: u16) {
-        self.index_register = (self.vx[((instruction & 0x0F00) >> 8) as usize].0 as u16)*5;
+    fn get_font_character(&mut self, x: u8) {
+        self.index_register = FONT_ADDRESS as u16 + (self.vx[x as usize].0 as u16 & 0xF) * 5;
+    }
+
+    fn get_big_font_character(&mut self, x: u8) {
+        self.index_register = self.big_font_address + (self.vx[x as usize].0 as u16 & 0xF) * 10;
     }
 
-    fn binary_to_decimal(&mut self, instruction: u16) {
-        let number = self.vx[((instruction & 0x0F00) >> 8) as usize].0;
-        
-        self.memory[self.index_register as usize] = number / 100;
-        self.memory[self.index_register as usize + 1] = (number / 10) % 10;
-        self.memory[self.index_register as usize + 2] = number % 10;
-        println!("{} {} {}", self.memory[self.index_register as usize], self.memory[self.index_register as usize + 1],self.memory[self.index_register as usize + 2])
+    fn binary_to_decimal(&mut self, x: u8) -> Result<(), EmuError> {
+        let number = self.vx[x as usize].0;
+        self.check_memory(self.index_register as usize, 3)?;
+
+        self.write_memory(self.index_register as usize, number / 100);
+        self.write_memory(self.index_register as usize + 1, (number / 10) % 10);
+        self.write_memory(self.index_register as usize + 2, number % 10);
+        Ok(())
     }
 
-    fn save_registers(&mut self, instruction: u16) {
-        let n = (instruction & 0x0F00) >> 8;
+    fn save_registers(&mut self, x: u8) -> Result<(), EmuError> {
+        let n = x as u16;
+        self.check_memory(self.index_register as usize, n as usize + 1)?;
         for i in 0..n + 1 {
-            self.memory[(self.index_register + i) as usize] = self.vx[i as usize].0;
+            self.write_memory((self.index_register + i) as usize, self.vx[i as usize].0);
         }
+        self.increment_index_after_load_store(n);
+        Ok(())
     }
 
-    fn load_registers(&mut self, instruction: u16) {
-        let n = (instruction & 0x0F00) >> 8;
+    fn load_registers(&mut self, x: u8) -> Result<(), EmuError> {
+        let n = x as u16;
+        self.check_memory(self.index_register as usize, n as usize + 1)?;
         for i in 0..n + 1{
             self.vx[i as usize] = Wrapping(self.memory[(self.index_register + i) as usize]);
         }
+        self.increment_index_after_load_store(n);
+        Ok(())
+    }
+
+    fn increment_index_after_load_store(&mut self, n: u16) {
+        match self.quirks.load_store {
+            LoadStoreTypes::Unchanged => {},
+            LoadStoreTypes::IncrementByX => self.index_register = self.index_register.wrapping_add(n),
+            LoadStoreTypes::IncrementByXPlusOne => self.index_register = self.index_register.wrapping_add(n + 1),
+        }
+    }
+
+    /// 5XY2 and 5XY3 walk the registers from X to Y, backwards if Y is below X.
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    fn save_register_range(&mut self, x: u8, y: u8) -> Result<(), EmuError> {
+        let registers = CPU::register_range(x, y);
+        self.check_memory(self.index_register as usize, registers.len())?;
+        for (i, register) in registers.into_iter().enumerate() {
+            self.write_memory(self.index_register as usize + i, self.vx[register].0);
+        }
+        Ok(())
+    }
+
+    fn load_register_range(&mut self, x: u8, y: u8) -> Result<(), EmuError> {
+        let registers = CPU::register_range(x, y);
+        self.check_memory(self.index_register as usize, registers.len())?;
+        for (i, register) in registers.into_iter().enumerate() {
+            self.vx[register] = Wrapping(self.memory[self.index_register as usize + i]);
+        }
+        Ok(())
+    }
+
+    fn select_planes(&mut self, mask: u8) {
+        self.display_array.select_planes(mask);
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<(), EmuError> {
+        let address = self.index_register as usize;
+        self.check_memory(address, 16)?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(&self.memory[address..address + 16]);
+        self.audio_pattern = Some(pattern);
+        self.beeper.set_pattern(self.audio_pattern, self.pitch);
+        Ok(())
+    }
+
+    fn set_pitch(&mut self, x: u8) {
+        self.pitch = self.vx[x as usize].0;
+        if self.audio_pattern.is_some() {
+            self.beeper.set_pattern(self.audio_pattern, self.pitch);
+        }
+    }
+
+    fn save_flags(&mut self, x: u8) {
+        let n = x as usize;
+        for i in 0..n + 1 {
+            self.rpl_flags[i] = self.vx[i].0;
+        }
+    }
+
+    fn load_flags(&mut self, x: u8) {
+        let n = x as usize;
+        for i in 0..n + 1 {
+            self.vx[i] = Wrapping(self.rpl_flags[i]);
+        }
+    }
+
+    fn skip_if_key(&mut self, x: u8) {
+        if self.is_key_down(self.vx[x as usize].0) {
+            self.skip_next();
+        }
+    }
+
+    fn skip_if_not_key(&mut self, x: u8) {
+        if !self.is_key_down(self.vx[x as usize].0) {
+            self.skip_next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::frontend::Hotkey;
+    use crate::headless::Headless;
+
+    /// A keypad whose held keys the test sets directly, one bit per key.
+    struct ScriptedKeypad(Rc<Cell<u16>>);
+
+    impl Keypad for ScriptedKeypad {
+        fn update(&mut self) -> bool {
+            true
+        }
+
+        fn is_key_down(&self, key: u8) -> bool {
+            self.0.get() & (1 << key) != 0
+        }
+
+        fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+            Vec::new()
+        }
+
+        fn is_rewind_held(&self) -> bool {
+            false
+        }
+    }
+
+    fn cpu_with(quirks: Quirks, program: &[u8]) -> CPU {
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(Headless), Box::new(Headless));
+        cpu.set_quirks(quirks);
+        cpu.load(program.to_vec()).unwrap();
+        cpu
+    }
+
+    /// The lit pixels on screen, row by row, with the plane colors they are lit in.
+    fn lit_pixels(cpu: &CPU) -> Vec<(usize, usize, usize)> {
+        let screen = cpu.framebuffer();
+        (0..screen.height())
+            .flat_map(|y| (0..screen.width()).map(move |x| (x, y, screen.color(x, y))))
+            .filter(|&(_, _, color)| color != 0)
+            .collect()
+    }
+
+    fn steps(cpu: &mut CPU, count: usize) {
+        for _ in 0..count {
+            cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn out_of_bounds_store_reports_first_address_past_memory() {
+        // LD I, LONG 0xFFFF; LD [I], V3
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[0xF0, 0x00, 0xFF, 0xFF, 0xF3, 0x55]);
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error, EmuError::MemoryOutOfBounds { pc: 0x204, opcode: 0xF355, address: 0x10000 });
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn memory_ends_at_4k_outside_xo_chip() {
+        // LD I, 0xFFF; LD [I], V3
+        let program = [0xAF, 0xFF, 0xF3, 0x55];
+        let mut cpu = cpu_with(Quirks::cosmac_vip(), &program);
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error, EmuError::MemoryOutOfBounds { pc: 0x202, opcode: 0xF355, address: 0x1000 });
+
+        let mut cpu = cpu_with(Quirks::xo_chip(), &program);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
     }
 
-    fn skip_if_key(&mut self, instruction: u16) {
-        if self.is_key_down(self.vx[((instruction & 0x0F00) >> 8) as usize].0) {
-            self.pc += 2;
+    #[test]
+    fn sprite_past_memory_faults() {
+        // LD I, LONG 0xFFFA; DRW V0, V1, 15
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[0xF0, 0x00, 0xFF, 0xFA, 0xD0, 0x1F]);
+        cpu.step().unwrap();
+        let error = cpu.step().unwrap_err();
+        assert_eq!(error, EmuError::MemoryOutOfBounds { pc: 0x204, opcode: 0xD01F, address: 0x10000 });
+    }
+
+    #[test]
+    fn key_wait_completes_on_release_of_a_new_press() {
+        let keys = Rc::new(Cell::new(1 << 0x5));
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(Headless), Box::new(ScriptedKeypad(keys.clone())));
+        // LD V3, K; JP 0x202
+        cpu.load(vec![0xF3, 0x0A, 0x12, 0x02]).unwrap();
+
+        // Key 5 was already down when FX0A started, so it doesn't count.
+        cpu.step().unwrap();
+        assert!(cpu.is_waiting_for_key());
+        keys.set(1 << 0x5 | 1 << 0x7);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert!(cpu.is_waiting_for_key(), "completes only once the key is let go");
+        assert_eq!(cpu.pc, 0x200);
+
+        keys.set(1 << 0x5);
+        cpu.step().unwrap();
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.vx[3].0, 0x7);
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn key_held_from_before_counts_once_pressed_again() {
+        let keys = Rc::new(Cell::new(1 << 0x5));
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(Headless), Box::new(ScriptedKeypad(keys.clone())));
+        cpu.load(vec![0xF3, 0x0A, 0x12, 0x02]).unwrap();
+
+        for held in [1 << 0x5, 0, 1 << 0x5] {
+            keys.set(held);
+            cpu.step().unwrap();
+            assert!(cpu.is_waiting_for_key());
         }
+        keys.set(0);
+        cpu.step().unwrap();
+        assert_eq!(cpu.vx[3].0, 0x5);
     }
 
-    fn skip_if_not_key(&mut self, instruction: u16) {
-        if !self.is_key_down(self.vx[((instruction & 0x0F00) >> 8) as usize].0) {
-            self.pc += 2;
+    #[test]
+    fn jump_with_offset_follows_quirk() {
+        // LD V0, 0x10; LD V1, 0x20; JP V0, 0x100
+        let program = [0x60, 0x10, 0x61, 0x20, 0xB1, 0x00];
+        for (quirks, target) in [(Quirks::cosmac_vip(), 0x110), (Quirks::super_chip(), 0x120)] {
+            let mut cpu = cpu_with(quirks, &program);
+            for _ in 0..3 {
+                cpu.step().unwrap();
+            }
+            assert_eq!(cpu.pc, target, "{:?}", quirks.jump);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn font_character_points_at_loaded_font() {
+        let font = Font::from_name("vip").unwrap();
+        for digit in 0..16u8 {
+            // LD V2, digit; LD F, V2
+            let mut cpu = cpu_with(Quirks::default(), &[0x62, digit, 0xF2, 0x29]);
+            cpu.set_font(&font, BIG_FONT_ADDRESS);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+            let address = cpu.index_register as usize;
+            assert_eq!(address, 0x50 + 5 * digit as usize);
+            assert_eq!(cpu.memory[address..address + 5], font.small[5 * digit as usize..][..5]);
+        }
+    }
+
+    #[test]
+    fn big_font_character_follows_moved_font() {
+        // LD V2, 0xB; LD HF, V2
+        let mut cpu = cpu_with(Quirks::super_chip(), &[0x62, 0x0B, 0xF2, 0x30]);
+        let font = Font::default();
+        cpu.set_font(&font, 0x100);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.index_register, 0x100 + 10 * 0xB);
+        assert_eq!(cpu.memory[0x16E..0x178], font.big[110..120]);
+        assert!(cpu.memory[0xA0..0x100].iter().all(|&byte| byte == 0), "old big font is cleared");
+    }
+
+    #[test]
+    fn stack_overflows_at_preset_depth() {
+        for (name, depth) in [("vip", 12), ("chip48", 16), ("schip", 16), ("xochip", 16)] {
+            // CALL 0x200, forever
+            let mut cpu = cpu_with(Quirks::from_name(name).unwrap(), &[0x22, 0x00]);
+            for _ in 0..depth {
+                cpu.step().unwrap();
+            }
+            assert_eq!(cpu.stack_depth(), depth, "{}", name);
+            assert_eq!(cpu.step(), Err(EmuError::StackOverflow { pc: 0x200, opcode: 0x2200 }), "{}", name);
+        }
+    }
+
+    #[test]
+    fn in_memory_stack_keeps_return_addresses_at_0xea0() {
+        let mut quirks = Quirks::cosmac_vip();
+        quirks.stack_in_memory = true;
+        // 200: CALL 0x204; 202: JP 0x202; 204: RET
+        let mut cpu = cpu_with(quirks, &[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory[0xEA0..0xEA2], [0x02, 0x02]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn return_with_empty_stack_underflows() {
+        let mut cpu = cpu_with(Quirks::default(), &[0x00, 0xEE]);
+        assert_eq!(cpu.step(), Err(EmuError::StackUnderflow { pc: 0x200, opcode: 0x00EE }));
+    }
+
+    #[test]
+    fn super_chip_scrolls_the_screen() {
+        // LD I, 0x210; LD V0, 8; LD V1, 8; DRW V0, V1, 1; SCD 3; SCR; SCL; SCL
+        let mut cpu = cpu_with(Quirks::super_chip(), &[
+            0xA2, 0x10, 0x60, 0x08, 0x61, 0x08, 0xD0, 0x11, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC,
+            0x80,
+        ]);
+        steps(&mut cpu, 4);
+        assert_eq!(lit_pixels(&cpu), vec![(8, 8, 1)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(&cpu), vec![(8, 11, 1)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(&cpu), vec![(12, 11, 1)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(&cpu), vec![(8, 11, 1)]);
+        steps(&mut cpu, 1);
+        assert_eq!(lit_pixels(&cpu), vec![(4, 11, 1)]);
+    }
+
+    #[test]
+    fn super_chip_resolution_switch_clears_the_screen() {
+        // LD I, 0x208; DRW V0, V0, 1; HIGH; LOW
+        let mut cpu = cpu_with(Quirks::super_chip(), &[0xA2, 0x08, 0xD0, 0x01, 0x00, 0xFF, 0x00, 0xFE, 0x80]);
+        steps(&mut cpu, 2);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 0, 1)]);
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.framebuffer().width(), cpu.framebuffer().height()), (128, 64));
+        assert!(lit_pixels(&cpu).is_empty());
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.framebuffer().width(), cpu.framebuffer().height()), (64, 32));
+    }
+
+    #[test]
+    fn super_chip_exit_halts() {
+        // EXIT; LD V0, 1
+        let mut cpu = cpu_with(Quirks::super_chip(), &[0x00, 0xFD, 0x60, 0x01]);
+        steps(&mut cpu, 1);
+        assert!(cpu.is_halted());
+        cpu.run_frame(10).unwrap();
+        assert_eq!((cpu.pc, cpu.vx[0].0), (0x202, 0));
+    }
+
+    #[test]
+    fn super_chip_draws_16x16_sprites() {
+        // HIGH; LD I, 0x20A; DRW V0, V0, 0; DRW V0, V0, 0; JP 0x208
+        let mut program = vec![0x00, 0xFF, 0xA2, 0x0A, 0xD0, 0x00, 0xD0, 0x00, 0x12, 0x08];
+        // A hollow column: the leftmost and rightmost pixel of all 16 rows.
+        program.extend([0x80, 0x01].repeat(16));
+        let mut cpu = cpu_with(Quirks::super_chip(), &program);
+        steps(&mut cpu, 3);
+        let lit = lit_pixels(&cpu);
+        assert_eq!(lit.len(), 32);
+        assert!([(0, 0, 1), (15, 0, 1), (0, 15, 1), (15, 15, 1)].iter().all(|pixel| lit.contains(pixel)));
+        assert_eq!(cpu.vx[0xF].0, 0);
+        steps(&mut cpu, 1);
+        assert!(lit_pixels(&cpu).is_empty());
+        assert_eq!(cpu.vx[0xF].0, 1);
+    }
+
+    #[test]
+    fn super_chip_flags_round_trip() {
+        // LD V0, 0x11; LD V1, 0x22; LD V2, 0x33; LD R, V2; LD V0, 0; LD V1, 0; LD V2, 0; LD V3, 0x44; LD V2, R
+        let mut cpu = cpu_with(Quirks::super_chip(), &[
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0x63, 0x44, 0xF2, 0x85,
+        ]);
+        steps(&mut cpu, 4);
+        assert_eq!(&cpu.rpl_flags[..4], &[0x11, 0x22, 0x33, 0]);
+        steps(&mut cpu, 5);
+        assert_eq!(cpu.vx[..4].iter().map(|register| register.0).collect::<Vec<_>>(), vec![0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn xo_chip_draws_on_selected_planes() {
+        // LD I, 0x20E; PLANE 2; DRW V0, V0, 1; PLANE 3; LD V1, 8; DRW V1, V1, 2; JP 0x20C
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[
+            0xA2, 0x0E, 0xF2, 0x01, 0xD0, 0x11, 0xF3, 0x01, 0x61, 0x08, 0xD1, 0x12, 0x12, 0x0C,
+            0x80, 0x40, 0xC0, 0x00,
+        ]);
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 0, 2)]);
+        // Both planes selected: two rows for the first plane, then two for the second.
+        steps(&mut cpu, 3);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 0, 2), (8, 8, 3), (9, 8, 2), (9, 9, 1)]);
+    }
+
+    #[test]
+    fn xo_chip_register_ranges_go_either_direction() {
+        // LD V1, 1; LD V2, 2; LD V3, 3; LD I, 0x300; SAVE V1, V3; SAVE V3, V1; LOAD V1, V3; LOAD V3, V1
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0x53, 0x12, 0x51, 0x33, 0x53, 0x13,
+        ]);
+        let registers = |cpu: &CPU| cpu.vx[1..4].iter().map(|register| register.0).collect::<Vec<_>>();
+        steps(&mut cpu, 5);
+        assert_eq!(&cpu.memory[0x300..0x303], &[1, 2, 3]);
+        steps(&mut cpu, 1);
+        assert_eq!(&cpu.memory[0x300..0x303], &[3, 2, 1]);
+        steps(&mut cpu, 1);
+        assert_eq!(registers(&cpu), vec![3, 2, 1]);
+        steps(&mut cpu, 1);
+        assert_eq!(registers(&cpu), vec![1, 2, 3]);
+        assert_eq!(cpu.index_register, 0x300);
+    }
+
+    #[test]
+    fn xo_chip_long_load_sets_index() {
+        // LD I, LONG 0x1234
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[0xF0, 0x00, 0x12, 0x34]);
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.index_register, cpu.pc), (0x1234, 0x204));
+    }
+
+    #[test]
+    fn skips_step_over_a_whole_long_load() {
+        // LD V0, 5; SE V0, 5; LD I, LONG 0x1234; SNE V0, 5; LD I, LONG 0x5678; LD V1, 1
+        let mut cpu = cpu_with(Quirks::xo_chip(), &[
+            0x60, 0x05, 0x30, 0x05, 0xF0, 0x00, 0x12, 0x34, 0x40, 0x05, 0xF0, 0x00, 0x56, 0x78, 0x61, 0x01,
+        ]);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x208);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x20A);
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.index_register, cpu.pc), (0x5678, 0x20E));
+    }
+
+    #[test]
+    fn shift_source_follows_quirk() {
+        // LD V1, 0x81; LD V2, 0x06; SHR V1, V2; LD V1, 0x81; SHL V1, V2
+        let program = [0x61, 0x81, 0x62, 0x06, 0x81, 0x26, 0x61, 0x81, 0x81, 0x2E];
+        for (quirks, right, left) in [(Quirks::cosmac_vip(), (0x03, 0), (0x0C, 0)), (Quirks::super_chip(), (0x40, 1), (0x02, 1))] {
+            let mut cpu = cpu_with(quirks, &program);
+            steps(&mut cpu, 3);
+            assert_eq!((cpu.vx[1].0, cpu.vx[0xF].0), right);
+            steps(&mut cpu, 2);
+            assert_eq!((cpu.vx[1].0, cpu.vx[0xF].0), left);
+        }
+    }
+
+    #[test]
+    fn logic_vf_reset_follows_quirk() {
+        for operation in [0x1, 0x2, 0x3] {
+            // LD VF, 5; OR/AND/XOR V1, V2
+            let program = [0x6F, 0x05, 0x81, 0x20 | operation];
+            for (quirks, vf) in [(Quirks::cosmac_vip(), 0), (Quirks::super_chip(), 5)] {
+                let mut cpu = cpu_with(quirks, &program);
+                steps(&mut cpu, 2);
+                assert_eq!(cpu.vx[0xF].0, vf, "8XY{}", operation);
+            }
+        }
+    }
+
+    #[test]
+    fn load_store_index_follows_quirk() {
+        // LD I, 0x300; LD [I], V2; LD I, 0x300; LD V2, [I]
+        let program = [0xA3, 0x00, 0xF2, 0x55, 0xA3, 0x00, 0xF2, 0x65];
+        for (quirks, index) in [(Quirks::cosmac_vip(), 0x303), (Quirks::chip48(), 0x302), (Quirks::super_chip(), 0x300)] {
+            let mut cpu = cpu_with(quirks, &program);
+            steps(&mut cpu, 2);
+            assert_eq!(cpu.index_register, index);
+            steps(&mut cpu, 2);
+            assert_eq!(cpu.index_register, index);
+        }
+    }
+
+    #[test]
+    fn sprites_clip_or_wrap_at_the_edges() {
+        // LD I, 0x20A; LD V0, 62; LD V1, 31; DRW V0, V1, 2; JP 0x208
+        let program = [0xA2, 0x0A, 0x60, 0x3E, 0x61, 0x1F, 0xD0, 0x12, 0x12, 0x08, 0xF0, 0xF0];
+        let clipped = vec![(62, 31, 1), (63, 31, 1)];
+        let wrapped = vec![(0, 0, 1), (1, 0, 1), (62, 0, 1), (63, 0, 1), (0, 31, 1), (1, 31, 1), (62, 31, 1), (63, 31, 1)];
+        for (quirks, lit) in [(Quirks::super_chip(), clipped), (Quirks::xo_chip(), wrapped)] {
+            let mut cpu = cpu_with(quirks, &program);
+            steps(&mut cpu, 4);
+            assert_eq!(lit_pixels(&cpu), lit);
+        }
+    }
+
+    #[test]
+    fn display_wait_stalls_drawing_until_the_frame_ends() {
+        // LD I, 0x206; DRW V0, V0, 1; JP 0x204
+        let program = [0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0x80];
+        let mut cpu = cpu_with(Quirks::cosmac_vip(), &program);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x202);
+        assert!(lit_pixels(&cpu).is_empty());
+        cpu.end_frame();
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 0, 1)]);
+
+        let mut cpu = cpu_with(Quirks::super_chip(), &program);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(lit_pixels(&cpu), vec![(0, 0, 1)]);
+    }
+}
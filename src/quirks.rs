@@ -0,0 +1,122 @@
+/// Which register 8XY6 and 8XYE shift.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ShiftTypes {
+    /// Shift VX in place, ignoring VY.
+    AsX,
+    /// Shift VY and store the result in VX.
+    AsY,
+}
+
+/// How BNNN picks the register added to the jump address.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum JumpTypes {
+    /// COSMAC VIP: jump to NNN + V0.
+    V0,
+    /// CHIP-48 and SUPER-CHIP: jump to XNN + VX.
+    VX,
+}
+
+/// What FX55 and FX65 leave in the index register.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LoadStoreTypes {
+    Unchanged,
+    IncrementByX,
+    IncrementByXPlusOne,
+}
+
+/// Behaviors that differ between CHIP-8 interpreters.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Quirks {
+    pub shift: ShiftTypes,
+    pub jump: JumpTypes,
+    pub load_store: LoadStoreTypes,
+    /// 8XY1, 8XY2 and 8XY3 clear VF.
+    pub vf_reset: bool,
+    /// Sprites are cut off at the screen edge instead of wrapping around.
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60 Hz tick before drawing.
+    pub display_wait: bool,
+    /// Nesting depth of 2NNN calls before the stack overflows.
+    pub stack_depth: usize,
+    /// Keep return addresses in emulated memory at 0xEA0, where the COSMAC VIP interpreter put them.
+    pub stack_in_memory: bool,
+    /// Bytes of addressable memory: 4K, or 64K on XO-CHIP. Reaching past it is a fault.
+    pub memory_size: usize,
+}
+
+pub const PRESET_NAMES: &str = "vip, chip48, schip, xochip";
+
+impl Quirks {
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift: ShiftTypes::AsY,
+            jump: JumpTypes::V0,
+            load_store: LoadStoreTypes::IncrementByXPlusOne,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            stack_depth: 12,
+            stack_in_memory: false,
+            memory_size: 0x1000,
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: ShiftTypes::AsX,
+            jump: JumpTypes::VX,
+            load_store: LoadStoreTypes::IncrementByX,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            stack_depth: 16,
+            stack_in_memory: false,
+            memory_size: 0x1000,
+        }
+    }
+
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift: ShiftTypes::AsX,
+            jump: JumpTypes::VX,
+            load_store: LoadStoreTypes::Unchanged,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            stack_depth: 16,
+            stack_in_memory: false,
+            memory_size: 0x1000,
+        }
+    }
+
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift: ShiftTypes::AsY,
+            jump: JumpTypes::V0,
+            load_store: LoadStoreTypes::IncrementByXPlusOne,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+            stack_depth: 16,
+            stack_in_memory: false,
+            memory_size: 0x10000,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::super_chip()),
+            "xochip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+}
+
+/// SUPER-CHIP, which matches how this emulator behaved before quirks were selectable.
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::super_chip()
+    }
+}
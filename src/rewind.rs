@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use crate::savestate::MachineState;
+
+/// The last few seconds of machine states, one per frame, for stepping back through gameplay.
+///
+/// Only the newest state is kept whole. Each older one is stored as the XOR of its bytes with the
+/// state after it, with the runs of zeros that leaves, from everything a frame didn't touch,
+/// squeezed out. A frame typically costs a few dozen bytes instead of the full 64K of memory.
+pub struct Rewind {
+    /// The newest state, as written by `MachineState::to_bytes`.
+    latest: Option<Vec<u8>>,
+    /// Older states, oldest first.
+    deltas: VecDeque<Vec<u8>>,
+    /// How many frames back rewinding can go.
+    capacity: usize,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind { latest: None, deltas: VecDeque::new(), capacity }
+    }
+
+    /// Records the state at the end of a frame, forgetting the oldest once full.
+    pub fn record(&mut self, state: &MachineState) {
+        if self.capacity == 0 {
+            return;
+        }
+        let bytes = state.to_bytes();
+        if let Some(previous) = self.latest.replace(bytes) {
+            self.deltas.push_back(encode(&previous, self.latest.as_ref().unwrap()));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    /// Drops the newest state and returns the one from the frame before it, until none are left.
+    pub fn step_back(&mut self) -> Option<MachineState> {
+        let delta = self.deltas.pop_back()?;
+        let latest = decode(self.latest.as_ref()?, &delta);
+        let state = MachineState::from_bytes(&latest).expect("Rewind history is corrupt");
+        self.latest = Some(latest);
+        Some(state)
+    }
+}
+
+/// `state` relative to `next`: its length, then pairs of a run of unchanged bytes and a run of
+/// changed ones, each run length a little-endian u16, followed by the changed bytes XORed.
+fn encode(state: &[u8], next: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = state.iter().enumerate().map(|(i, byte)| byte ^ next.get(i).unwrap_or(&0)).collect();
+    let mut delta = (state.len() as u32).to_le_bytes().to_vec();
+    let mut position = 0;
+    while position < xor.len() {
+        let unchanged = xor[position..].iter().take(u16::MAX as usize).take_while(|&&byte| byte == 0).count();
+        position += unchanged;
+        let changed = xor[position..].iter().take(u16::MAX as usize).take_while(|&&byte| byte != 0).count();
+        delta.extend_from_slice(&(unchanged as u16).to_le_bytes());
+        delta.extend_from_slice(&(changed as u16).to_le_bytes());
+        delta.extend_from_slice(&xor[position..position + changed]);
+        position += changed;
+    }
+    delta
+}
+
+/// Rebuilds the state `delta` was encoded from, given the state after it.
+fn decode(next: &[u8], delta: &[u8]) -> Vec<u8> {
+    let length = u32::from_le_bytes(delta[0..4].try_into().unwrap()) as usize;
+    let mut state: Vec<u8> = (0..length).map(|i| *next.get(i).unwrap_or(&0)).collect();
+    let mut position = 0;
+    let mut runs = &delta[4..];
+    while let [unchanged_low, unchanged_high, changed_low, changed_high, rest @ ..] = runs {
+        position += u16::from_le_bytes([*unchanged_low, *unchanged_high]) as usize;
+        let changed = u16::from_le_bytes([*changed_low, *changed_high]) as usize;
+        for (byte, xor) in state[position..position + changed].iter_mut().zip(&rest[..changed]) {
+            *byte ^= xor;
+        }
+        position += changed;
+        runs = &rest[changed..];
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::headless::Headless;
+    use crate::processor::CPU;
+
+    #[test]
+    fn delta_round_trip() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for _ in 0..50 {
+            let next: Vec<u8> = (0..rng.gen_range(0..70_000)).map(|_| if rng.gen_bool(0.01) { rng.gen() } else { 0 }).collect();
+            let mut state: Vec<u8> = (0..rng.gen_range(0..70_000))
+                .map(|i| if rng.gen_bool(0.001) { rng.gen() } else { *next.get(i).unwrap_or(&0) })
+                .collect();
+            if rng.gen_bool(0.2) {
+                rng.fill(&mut state[..]);
+            }
+            assert_eq!(decode(&next, &encode(&state, &next)), state);
+        }
+    }
+
+    #[test]
+    fn unchanged_state_encodes_small() {
+        let state = vec![7; 70_000];
+        assert!(encode(&state, &state).len() < 16);
+    }
+
+    #[test]
+    fn steps_back_through_recorded_frames() {
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(Headless), Box::new(Headless));
+        cpu.load(include_bytes!("../ROMs/IBM.ch8").to_vec()).unwrap();
+        let mut rewind = Rewind::new(30);
+        let mut states = Vec::new();
+        for _ in 0..40 {
+            cpu.run_frame(2).unwrap();
+            rewind.record(&cpu.save_state());
+            states.push(cpu.save_state());
+        }
+        // Only the last 30 frames before the newest are kept.
+        for back in 1..=30 {
+            assert!(rewind.step_back().unwrap() == states[39 - back], "{} frames back", back);
+        }
+        assert!(rewind.step_back().is_none());
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::{Framebuffer, HIRES_HEIGHT, PLANE_COUNT};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+/// Bump whenever the layout written by `MachineState::to_bytes` changes.
+const VERSION: u16 = 1;
+
+/// Everything needed to put the machine back exactly where it was.
+#[derive(Clone, PartialEq)]
+pub struct MachineState {
+    pub memory: Vec<u8>,
+    pub vx: [u8; 16],
+    pub stack_register: Vec<u16>,
+    pub pc: u16,
+    pub index_register: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub display_array: Framebuffer,
+    pub vblank: bool,
+    pub rpl_flags: [u8; 16],
+    pub halted: bool,
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+}
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(std::io::Error),
+    NotASaveState,
+    UnsupportedVersion(u16),
+    WrongRom,
+    Truncated,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(error) => write!(f, "{}", error),
+            SaveStateError::NotASaveState => write!(f, "not a save state file"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            SaveStateError::WrongRom => write!(f, "save state was taken from a different ROM"),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveStateError {
+    fn from(error: std::io::Error) -> SaveStateError {
+        SaveStateError::Io(error)
+    }
+}
+
+/// FNV-1a hash identifying the ROM a state belongs to.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF29CE484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001B3))
+}
+
+/// Where slot `slot` of a ROM is stored: next to the ROM, as `<rom>.state<slot>`.
+pub fn slot_path(rom_path: &str, slot: u8) -> PathBuf {
+    PathBuf::from(format!("{}.state{}", rom_path, slot))
+}
+
+pub fn save(path: &Path, rom_hash: u64, state: &MachineState) -> Result<(), SaveStateError> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&rom_hash.to_le_bytes());
+    bytes.extend_from_slice(&state.to_bytes());
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+pub fn load(path: &Path, rom_hash: u64) -> Result<MachineState, SaveStateError> {
+    let bytes = std::fs::read(path)?;
+    let mut reader = Reader { bytes: &bytes, position: 0 };
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    if reader.u64()? != rom_hash {
+        return Err(SaveStateError::WrongRom);
+    }
+    MachineState::read(&mut reader)
+}
+
+impl MachineState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.memory.len() + 4096);
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.memory);
+        bytes.extend_from_slice(&self.vx);
+        bytes.extend_from_slice(&(self.stack_register.len() as u16).to_le_bytes());
+        for address in &self.stack_register {
+            bytes.extend_from_slice(&address.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.pc.to_le_bytes());
+        bytes.extend_from_slice(&self.index_register.to_le_bytes());
+        bytes.push(self.delay_timer);
+        bytes.push(self.sound_timer);
+
+        let (planes, selected_planes, hires) = self.display_array.parts();
+        bytes.push(hires as u8);
+        bytes.push(selected_planes);
+        for plane in planes {
+            for row in plane {
+                bytes.extend_from_slice(&row.to_le_bytes());
+            }
+        }
+
+        bytes.push(self.vblank as u8);
+        bytes.extend_from_slice(&self.rpl_flags);
+        bytes.push(self.halted as u8);
+        match self.audio_pattern {
+            Some(pattern) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&pattern);
+            },
+            None => bytes.push(0),
+        }
+        bytes.push(self.pitch);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MachineState, SaveStateError> {
+        MachineState::read(&mut Reader { bytes, position: 0 })
+    }
+
+    fn read(reader: &mut Reader) -> Result<MachineState, SaveStateError> {
+        let memory_size = reader.u32()? as usize;
+        let memory = reader.bytes(memory_size)?.to_vec();
+        let vx = reader.array()?;
+        let stack_size = reader.u16()?;
+        let mut stack_register = Vec::with_capacity(stack_size as usize);
+        for _ in 0..stack_size {
+            stack_register.push(reader.u16()?);
+        }
+        let pc = reader.u16()?;
+        let index_register = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+
+        let hires = reader.u8()? != 0;
+        let selected_planes = reader.u8()?;
+        let mut planes = [[0; HIRES_HEIGHT]; PLANE_COUNT];
+        for plane in planes.iter_mut() {
+            for row in plane.iter_mut() {
+                *row = u128::from_le_bytes(reader.array()?);
+            }
+        }
+
+        let vblank = reader.u8()? != 0;
+        let rpl_flags = reader.array()?;
+        let halted = reader.u8()? != 0;
+        let audio_pattern = match reader.u8()? {
+            0 => None,
+            _ => Some(reader.array()?),
+        };
+        let pitch = reader.u8()?;
+
+        Ok(MachineState {
+            memory,
+            vx,
+            stack_register,
+            pc,
+            index_register,
+            delay_timer,
+            sound_timer,
+            display_array: Framebuffer::from_parts(planes, selected_planes, hires),
+            vblank,
+            rpl_flags,
+            halted,
+            audio_pattern,
+            pitch,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], SaveStateError> {
+        let bytes = self.bytes.get(self.position..self.position + count).ok_or(SaveStateError::Truncated)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::processor::CPU;
+
+    const ROM_HASH: u64 = 0x1234_5678;
+
+    fn sample_state() -> MachineState {
+        let mut cpu = CPU::new(Box::new(Headless), Box::new(Headless), Box::new(Headless));
+        cpu.load(include_bytes!("../ROMs/IBM.ch8").to_vec()).unwrap();
+        for _ in 0..5 {
+            cpu.run_frame(10).unwrap();
+        }
+        let mut state = cpu.save_state();
+        state.vx[0xF] = 1;
+        state.stack_register = vec![0x202, 0x3FE];
+        state.delay_timer = 7;
+        state.sound_timer = 3;
+        state.rpl_flags[2] = 0xAB;
+        state.audio_pattern = Some([0x55; 16]);
+        state.pitch = 90;
+        state
+    }
+
+    /// A save file in the temp directory, removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            TempFile(std::env::temp_dir().join(format!("chip8-{}-{}.state", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Saves `sample_state`, lets `corrupt` change the file, then loads it.
+    fn load_corrupted(name: &str, corrupt: impl FnOnce(&mut Vec<u8>)) -> Result<MachineState, SaveStateError> {
+        let file = TempFile::new(name);
+        save(&file.0, ROM_HASH, &sample_state()).unwrap();
+        let mut bytes = std::fs::read(&file.0).unwrap();
+        corrupt(&mut bytes);
+        std::fs::write(&file.0, bytes).unwrap();
+        load(&file.0, ROM_HASH)
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let state = sample_state();
+        assert!(MachineState::from_bytes(&state.to_bytes()).unwrap() == state);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        assert!(load_corrupted("round-trip", |_| {}).unwrap() == sample_state());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let result = load_corrupted("magic", |bytes| bytes[0] = b'X');
+        assert!(matches!(result, Err(SaveStateError::NotASaveState)));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let result = load_corrupted("version", |bytes| bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes()));
+        assert!(matches!(result, Err(SaveStateError::UnsupportedVersion(version)) if version == VERSION + 1));
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let file = TempFile::new("rom");
+        save(&file.0, ROM_HASH, &sample_state()).unwrap();
+        assert!(matches!(load(&file.0, ROM_HASH + 1), Err(SaveStateError::WrongRom)));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let result = load_corrupted("truncated", |bytes| bytes.truncate(bytes.len() - 1));
+        assert!(matches!(result, Err(SaveStateError::Truncated)));
+        let result = load_corrupted("header", |bytes| bytes.truncate(10));
+        assert!(matches!(result, Err(SaveStateError::Truncated)));
+    }
+}
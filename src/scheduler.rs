@@ -0,0 +1,44 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FRAMES_PER_SECOND: u64 = 60;
+/// Most frames run back to back after a stall; anything older is dropped rather than fast-forwarded.
+const MAX_CATCH_UP: u64 = 4;
+
+/// Paces the main loop at 60 Hz from a monotonic clock.
+///
+/// Frame N is due exactly N/60 s after the start, so the rate does not drift. A loop that stalls
+/// for up to `MAX_CATCH_UP` frames (about 67 ms) runs the frames it missed instead of losing timer
+/// ticks. Longer stalls, like dragging the window or sitting in the debugger, deliberately lose the
+/// rest: the game picks up where it was rather than fast-forwarding through the gap.
+pub struct FrameScheduler {
+    start: Instant,
+    frames: u64,
+}
+
+impl FrameScheduler {
+    pub fn new() -> FrameScheduler {
+        FrameScheduler {
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    /// Sleeps until the next frame is due and returns how many frames to run, at least one.
+    pub fn wait(&mut self) -> u64 {
+        let next = self.frame_time(self.frames + 1);
+        let elapsed = self.start.elapsed();
+        if elapsed < next {
+            thread::sleep(next - elapsed);
+        }
+
+        let due = self.start.elapsed().as_nanos() as u64 * FRAMES_PER_SECOND / 1_000_000_000;
+        let frames = due.saturating_sub(self.frames).clamp(1, MAX_CATCH_UP);
+        self.frames = due.max(self.frames + frames);
+        frames
+    }
+
+    fn frame_time(&self, frame: u64) -> Duration {
+        Duration::from_nanos(frame * 1_000_000_000 / FRAMES_PER_SECOND)
+    }
+}
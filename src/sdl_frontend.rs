@@ -0,0 +1,286 @@
+use sdl2::audio::AudioQueue;
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, GameControllerSubsystem};
+
+use crate::audio::Tone;
+use crate::config::Config;
+use crate::framebuffer::{Framebuffer, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::palette::Palette;
+use crate::frontend::{Beeper, Hotkey, Keypad, Screen};
+
+pub struct SdlScreen {
+    /// Sized for hi-res; lo-res frames only fill its top-left corner. Declared before `canvas`
+    /// so it is destroyed before the renderer that owns it.
+    texture: Texture,
+    canvas: Canvas<Window>,
+    /// Indexed by `Framebuffer::color`.
+    colors: [[u8; 3]; 4],
+    /// Scale by whole multiples only, for evenly sized pixels, instead of filling the window.
+    integer_scale: bool,
+    /// How much of the previous frame's color each pixel keeps, emulating CRT phosphor persistence
+    /// to hide the flicker of XOR-drawn sprites. 0 shows every frame as is.
+    persistence: f32,
+    /// The blended color on screen for every pixel, row by row at the current resolution.
+    glow: Vec<[f32; 3]>,
+}
+
+impl SdlScreen {
+    pub fn new(canvas: Canvas<Window>, palette: &Palette, integer_scale: bool, persistence: f32) -> SdlScreen {
+        let texture = canvas.texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, HIRES_WIDTH as u32, HIRES_HEIGHT as u32)
+            .expect("Failed to create screen texture");
+        SdlScreen { texture, canvas, colors: palette.colors, integer_scale, persistence, glow: Vec::new() }
+    }
+
+    /// The largest area with the framebuffer's aspect ratio that fits the window, centered.
+    fn viewport(&self, framebuffer: &Framebuffer) -> Rect {
+        let (window_width, window_height) = self.canvas.output_size().expect("Failed to get window size");
+        let (width, height) = (framebuffer.width() as u32, framebuffer.height() as u32);
+        let (viewport_width, viewport_height) = if self.integer_scale {
+            let scale = (window_width / width).min(window_height / height).max(1);
+            (width * scale, height * scale)
+        } else if window_width * height > window_height * width {
+            (window_height * width / height, window_height)
+        } else {
+            (window_width, window_width * height / width)
+        };
+        Rect::new(
+            (window_width as i32 - viewport_width as i32) / 2,
+            (window_height as i32 - viewport_height as i32) / 2,
+            viewport_width.max(1),
+            viewport_height.max(1),
+        )
+    }
+}
+
+impl Screen for SdlScreen {
+    fn draw(&mut self, framebuffer: &Framebuffer) {
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let colors = self.colors;
+        let persistence = self.persistence;
+        // Only blend frames of the same resolution; a mode switch starts from a clean screen.
+        if self.glow.len() != width * height {
+            self.glow = vec![[0.0; 3]; width * height];
+            for (i, glow) in self.glow.iter_mut().enumerate() {
+                *glow = colors[framebuffer.color(i % width, i / width)].map(f32::from);
+            }
+        }
+        let glow = &mut self.glow;
+        self.texture.with_lock(Rect::new(0, 0, width as u32, height as u32), |pixels, pitch| {
+            for (row, line) in pixels.chunks_mut(pitch).take(height).enumerate() {
+                for (column, pixel) in line.chunks_exact_mut(3).take(width).enumerate() {
+                    let color = colors[framebuffer.color(column, row)];
+                    if persistence == 0.0 {
+                        pixel.copy_from_slice(&color);
+                        continue;
+                    }
+                    let glow = &mut glow[row * width + column];
+                    for channel in 0..3 {
+                        let target = color[channel] as f32;
+                        glow[channel] = target + (glow[channel] - target) * persistence;
+                        pixel[channel] = glow[channel].round() as u8;
+                    }
+                }
+            }
+        }).expect("Failed to update screen texture");
+
+        let viewport = self.viewport(framebuffer);
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, Rect::new(0, 0, width as u32, height as u32), viewport)
+            .expect("Failed to draw screen texture");
+        self.canvas.present();
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.colors = palette.colors;
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(error) = window.set_fullscreen(fullscreen) {
+            eprintln!("Failed to toggle fullscreen: {}", error);
+        }
+    }
+}
+
+/// Frames of sound queued ahead before new ones are dropped, so the host's audio clock running
+/// slower than the frame scheduler can't build up lag.
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+/// Queues each emulated frame's samples as it finishes. While emulation is paused nothing is
+/// queued, and the device plays silence.
+pub struct SdlBeeper {
+    queue: AudioQueue<f32>,
+    tone: Tone,
+}
+
+impl SdlBeeper {
+    pub fn new(queue: AudioQueue<f32>, tone: Tone) -> SdlBeeper {
+        queue.resume();
+        SdlBeeper { queue, tone }
+    }
+}
+
+impl Beeper for SdlBeeper {
+    fn play_frame(&mut self, beeping: bool) {
+        let samples = self.tone.render_frame(beeping);
+        let queued = self.queue.size() as usize / std::mem::size_of::<f32>();
+        if queued > samples.len() * MAX_QUEUED_FRAMES as usize {
+            return;
+        }
+        // After running dry, keep a frame of silence in hand to absorb scheduling jitter.
+        if queued == 0 {
+            self.queue.queue_audio(&vec![0.0; samples.len()]).expect("Failed to queue audio");
+        }
+        self.queue.queue_audio(&samples).expect("Failed to queue audio");
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.tone.set_pattern(pattern, pitch);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.tone.set_muted(muted);
+    }
+}
+
+pub struct SdlKeypad {
+    event_pump: EventPump,
+    /// Physical keys for CHIP-8 keys 0-F.
+    scancodes: [Scancode; 16],
+    controller_subsystem: GameControllerSubsystem,
+    /// Every connected controller feeds the same keypad.
+    controllers: Vec<GameController>,
+    /// Controller buttons and the CHIP-8 keys they press.
+    buttons: Vec<(Button, u8)>,
+    hotkeys: Vec<Hotkey>,
+    quit: bool,
+}
+
+impl SdlKeypad {
+    /// Maps each CHIP-8 key to the named key on the current keyboard layout, and controller buttons
+    /// to CHIP-8 keys. Controllers are opened as SDL reports them connected.
+    pub fn new(event_pump: EventPump, controller_subsystem: GameControllerSubsystem, config: &Config) -> Result<SdlKeypad, String> {
+        let mut scancodes = [Scancode::X; 16];
+        for (scancode, name) in scancodes.iter_mut().zip(&config.keypad) {
+            *scancode = Keycode::from_name(name)
+                .and_then(Scancode::from_keycode)
+                .or_else(|| Scancode::from_name(name))
+                .ok_or(format!("unknown key name {:?}", name))?;
+            if is_reserved(*scancode) {
+                return Err(format!("key {:?} is reserved for emulator hotkeys", name));
+            }
+        }
+        let mut buttons = Vec::with_capacity(config.gamepad.len());
+        for (name, key) in &config.gamepad {
+            let button = Button::from_string(name).ok_or(format!("unknown gamepad button {:?}", name))?;
+            buttons.push((button, *key));
+        }
+        Ok(SdlKeypad {
+            event_pump,
+            scancodes,
+            controller_subsystem,
+            controllers: Vec::new(),
+            buttons,
+            hotkeys: Vec::new(),
+            quit: false,
+        })
+    }
+}
+
+/// Held to rewind.
+const REWIND_KEY: Scancode = Scancode::Backspace;
+
+/// Keys the emulator itself answers to, which can't double as CHIP-8 keys.
+fn is_reserved(scancode: Scancode) -> bool {
+    scancode == REWIND_KEY || match Keycode::from_scancode(scancode) {
+        Some(keycode) => keycode == Keycode::Escape || keycode_to_hotkey(keycode, Mod::NOMOD).is_some(),
+        None => false,
+    }
+}
+
+/// F1-F9 load the matching save state slot, Shift+F1-F9 save to it. `+` and `-` change the speed,
+/// F10 switches to the next palette and F11 or Alt+Enter toggles fullscreen.
+fn keycode_to_hotkey(keycode: Keycode, keymod: Mod) -> Option<Hotkey> {
+    let slot = match keycode {
+        Keycode::F10 => return Some(Hotkey::NextPalette),
+        Keycode::F11 => return Some(Hotkey::ToggleFullscreen),
+        Keycode::F12 => return Some(Hotkey::ToggleMute),
+        Keycode::Return if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => return Some(Hotkey::ToggleFullscreen),
+        Keycode::Equals | Keycode::Plus => return Some(Hotkey::SpeedUp),
+        Keycode::Minus => return Some(Hotkey::SlowDown),
+        Keycode::F1 => 1,
+        Keycode::F2 => 2,
+        Keycode::F3 => 3,
+        Keycode::F4 => 4,
+        Keycode::F5 => 5,
+        Keycode::F6 => 6,
+        Keycode::F7 => 7,
+        Keycode::F8 => 8,
+        Keycode::F9 => 9,
+        _ => return None,
+    };
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        Some(Hotkey::SaveState(slot))
+    } else {
+        Some(Hotkey::LoadState(slot))
+    }
+}
+
+impl Keypad for SdlKeypad {
+    fn update(&mut self) -> bool {
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    self.quit = true;
+                },
+                Event::ControllerDeviceAdded { which, .. } => match self.controller_subsystem.open(which) {
+                    Ok(controller) => {
+                        println!("Connected controller: {}", controller.name());
+                        self.controllers.push(controller);
+                    },
+                    Err(error) => eprintln!("Failed to open controller {}: {}", which, error),
+                },
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|controller| controller.instance_id() != which);
+                },
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(hotkey) = keycode_to_hotkey(keycode, keymod) {
+                        self.hotkeys.push(hotkey);
+                    }
+                },
+                _ => {}
+            }
+        }
+        !self.quit
+    }
+
+    fn is_key_down(&self, key: u8) -> bool {
+        let keyboard = match self.scancodes.get(key as usize) {
+            Some(&scancode) => self.event_pump.keyboard_state().is_scancode_pressed(scancode),
+            None => false,
+        };
+        keyboard || self.buttons.iter()
+            .filter(|&&(_, bound_key)| bound_key == key)
+            .any(|&(button, _)| self.controllers.iter().any(|controller| controller.button(button)))
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn is_rewind_held(&self) -> bool {
+        self.event_pump.keyboard_state().is_scancode_pressed(REWIND_KEY)
+    }
+}
@@ -0,0 +1,27 @@
+//! Runs ROMs through the `--headless` command line and compares the final screen with a known
+//! good one, so regressions show up in CI.
+
+use std::process::Command;
+
+fn run_headless(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .arg("--headless")
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Unable to run chip8");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn ibm_logo() {
+    assert_eq!(run_headless(&["ROMs/IBM.ch8"]), include_str!("ibm.txt"));
+}
+
+#[test]
+fn ibm_logo_on_every_preset() {
+    for preset in ["vip", "chip48", "schip", "xochip"] {
+        assert_eq!(run_headless(&["--quirks", preset, "ROMs/IBM.ch8"]), include_str!("ibm.txt"), "{}", preset);
+    }
+}